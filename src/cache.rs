@@ -0,0 +1,274 @@
+use std::{
+    fs::{self, File},
+    io::Seek,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use reqwest::Url;
+use semver::Version;
+use tempfile::NamedTempFile;
+
+use crate::grinder::AtPack;
+
+const CACHE_DIRECTORY: &str = "atpacks-svd-harvester";
+const PAGES_DIRECTORY: &str = "pages";
+
+/// Persistent storage of downloaded ATPACKs, laid out as `<family>/<version>/<archive>`
+///
+/// Repository pages are kept under `pages/<host>/` so the harvester can run offline.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    root: PathBuf,
+}
+
+/// Single ATPACK archive stored in the cache
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    family: String,
+    version: Version,
+    path: PathBuf,
+    size: u64,
+}
+
+impl CacheEntry {
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Writes through a temporary file, so an interrupted run never leaves truncated entries
+fn write_atomically(path: &Path, content: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".part");
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+impl Cache {
+    pub fn new(root: &Path) -> Cache {
+        Cache {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Cache directory of the user, eg. `$XDG_CACHE_HOME/atpacks-svd-harvester` on Linux
+    pub fn default_location() -> Option<PathBuf> {
+        dirs::cache_dir().map(|d| d.join(CACHE_DIRECTORY))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Location of the ATPACK's archive in the cache
+    pub fn pack_path(&self, pack: &AtPack) -> PathBuf {
+        let archive = pack.archive().rsplit('/').next().unwrap_or(pack.archive());
+        self.root
+            .join(pack.family())
+            .join(pack.version().semver().to_string())
+            .join(archive)
+    }
+
+    fn page_path(&self, address: &Url, page: &str) -> PathBuf {
+        self.root
+            .join(PAGES_DIRECTORY)
+            .join(address.host_str().unwrap_or("local"))
+            .join(page)
+    }
+
+    pub fn load_pack(&self, pack: &AtPack) -> Result<Option<File>, Error> {
+        let path = self.pack_path(pack);
+        if path.is_file() {
+            Ok(Some(File::open(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Temporary file on the same file system as the cache, for downloads to be persisted
+    pub fn temporary_file(&self) -> Result<NamedTempFile, Error> {
+        fs::create_dir_all(&self.root)?;
+        Ok(NamedTempFile::new_in(&self.root)?)
+    }
+
+    /// Moves downloaded archive into the cache and returns it ready for reading
+    pub fn persist_pack(&self, pack: &AtPack, temporary: NamedTempFile) -> Result<File, Error> {
+        let path = self.pack_path(pack);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = temporary.persist(&path)?;
+        file.rewind()?;
+        Ok(file)
+    }
+
+    pub fn load_page(&self, address: &Url, page: &str) -> Result<Option<String>, Error> {
+        let path = self.page_path(address, page);
+        if path.is_file() {
+            Ok(Some(fs::read_to_string(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn store_page(&self, address: &Url, page: &str, content: &str) -> Result<(), Error> {
+        write_atomically(&self.page_path(address, page), content.as_bytes())
+    }
+
+    /// Lists cached ATPACKs ordered by family and from the newest version
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, Error> {
+        let mut entries = Vec::<CacheEntry>::new();
+        if !self.root.is_dir() {
+            return Ok(entries);
+        }
+
+        for family in fs::read_dir(&self.root)? {
+            let family = family?;
+            if !family.file_type()?.is_dir() || family.file_name() == PAGES_DIRECTORY {
+                continue;
+            }
+            for version in fs::read_dir(family.path())? {
+                let version = version?;
+                let Ok(parsed) = Version::parse(&version.file_name().to_string_lossy()) else {
+                    continue;
+                };
+                for archive in fs::read_dir(version.path())? {
+                    let archive = archive?;
+                    let metadata = archive.metadata()?;
+                    if !metadata.is_file() || archive.path().extension() == Some("part".as_ref()) {
+                        continue;
+                    }
+                    entries.push(CacheEntry {
+                        family: family.file_name().to_string_lossy().to_string(),
+                        version: parsed.clone(),
+                        path: archive.path(),
+                        size: metadata.len(),
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| {
+            a.family
+                .cmp(&b.family)
+                .then_with(|| b.version.cmp(&a.version))
+        });
+
+        Ok(entries)
+    }
+
+    /// Removes all but `keep` newest versions of each family, returns removed entries
+    pub fn prune(&self, keep: usize) -> Result<Vec<CacheEntry>, Error> {
+        let mut removed = vec![];
+        let mut kept = Vec::<(String, Version)>::new();
+
+        for entry in self.entries()? {
+            let key = (entry.family.clone(), entry.version.clone());
+            if kept.contains(&key) {
+                continue;
+            }
+            if kept.iter().filter(|(f, _)| *f == entry.family).count() < keep {
+                kept.push(key);
+                continue;
+            }
+
+            let directory = entry
+                .path
+                .parent()
+                .ok_or_else(|| anyhow!("Cache entry {} has no parent", entry.path.display()))?;
+            fs::remove_dir_all(directory)?;
+            removed.push(entry);
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use reqwest::Url;
+    use semver::Version;
+    use tempfile::TempDir;
+
+    use super::Cache;
+    use crate::grinder::{collection, AtPack};
+
+    /// Caches the pack the way downloads do
+    fn store_pack(cache: &Cache, pack: &AtPack) {
+        let mut temporary = cache.temporary_file().unwrap();
+        temporary.write_all(b"PK\x03\x04").unwrap();
+        let mut file = cache.persist_pack(pack, temporary).unwrap();
+        let mut content = vec![];
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"PK\x03\x04");
+    }
+
+    #[test]
+    fn packs_round_trip() {
+        let tempdir = TempDir::with_prefix("atpack-cache").expect("Temporary directory failed");
+        let cache = Cache::new(tempdir.path());
+        let collection = collection();
+        let pack = &collection.packs()[0];
+
+        assert!(cache.load_pack(pack).unwrap().is_none());
+        store_pack(&cache, pack);
+        let mut content = vec![];
+        let mut file = cache.load_pack(pack).unwrap().expect("Pack not cached");
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"PK\x03\x04");
+        assert!(cache
+            .pack_path(pack)
+            .ends_with("SAMV71/4.9.117/Microchip.SAMV71_DFP.4.9.117.atpack"));
+
+        let address = Url::parse("https://packs.download.microchip.com/").unwrap();
+        cache.store_page(&address, "index.idx", "<index/>").unwrap();
+        assert_eq!(
+            cache.load_page(&address, "index.idx").unwrap().as_deref(),
+            Some("<index/>")
+        );
+    }
+
+    #[test]
+    fn pruning_keeps_newest() {
+        let tempdir = TempDir::with_prefix("atpack-cache").expect("Temporary directory failed");
+        let cache = Cache::new(tempdir.path());
+        let collection = collection();
+        for pack in collection.packs() {
+            store_pack(&cache, pack);
+        }
+
+        let versions = cache
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|e| e.version().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(versions, ["4.12.237", "4.10.230", "4.9.117", "3.4.84"]);
+
+        let removed = cache.prune(1).unwrap();
+        assert_eq!(removed.len(), 3);
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].version(), &Version::new(4, 12, 237));
+    }
+}
@@ -0,0 +1,33 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of the whole input, as lowercase hexadecimal digits
+pub fn sha256(input: &mut impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(input, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    sha256(&mut File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sha256;
+
+    #[test]
+    fn check_sha256() {
+        assert_eq!(
+            sha256(&mut "abc".as_bytes()).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
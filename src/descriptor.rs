@@ -0,0 +1,617 @@
+use std::fmt::{self, Display};
+use std::io::{BufReader, Read, Seek};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer};
+use zip::ZipArchive;
+
+use crate::extractor::{read_entry, ExtractionLimits};
+use crate::version::PackVersion;
+
+/// Pack description file (`.pdsc`) as defined by CMSIS-Pack, bundled in every ATPACK
+#[derive(Debug, Deserialize)]
+#[serde(rename = "package")]
+pub struct PackDescriptor {
+    vendor: String,
+    name: String,
+    description: Option<String>,
+    releases: Releases,
+    #[serde(default)]
+    devices: Devices,
+}
+
+#[derive(Debug, Deserialize)]
+struct Releases {
+    #[serde(default)]
+    release: Vec<Release>,
+}
+
+/// Entry of the pack's release history
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    #[serde(rename = "@version")]
+    version: String,
+    #[serde(rename = "@date")]
+    date: Option<String>,
+    #[serde(rename = "$text")]
+    description: Option<String>,
+}
+
+impl Release {
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref().map(str::trim)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Devices {
+    #[serde(default)]
+    family: Vec<DeviceFamily>,
+}
+
+/// Properties which can be given on any level of the devices hierarchy, nearer one wins
+struct Properties<'a> {
+    processor: &'a [Processor],
+    debug: &'a [Debug],
+    memory: &'a [Memory],
+}
+
+// Flattening isn't supported for lists by the deserializer, so every level repeats them
+macro_rules! properties {
+    ($level:expr) => {
+        Properties {
+            processor: &$level.processor,
+            debug: &$level.debug,
+            memory: &$level.memory,
+        }
+    };
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceFamily {
+    #[serde(rename = "@Dfamily")]
+    name: String,
+    #[serde(default)]
+    processor: Vec<Processor>,
+    #[serde(default)]
+    debug: Vec<Debug>,
+    #[serde(default)]
+    memory: Vec<Memory>,
+    #[serde(default, rename = "subFamily")]
+    sub_family: Vec<DeviceSubFamily>,
+    #[serde(default)]
+    device: Vec<Device>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceSubFamily {
+    #[serde(rename = "@DsubFamily")]
+    name: String,
+    #[serde(default)]
+    processor: Vec<Processor>,
+    #[serde(default)]
+    debug: Vec<Debug>,
+    #[serde(default)]
+    memory: Vec<Memory>,
+    #[serde(default)]
+    device: Vec<Device>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Device {
+    #[serde(rename = "@Dname")]
+    name: String,
+    #[serde(default)]
+    processor: Vec<Processor>,
+    #[serde(default)]
+    debug: Vec<Debug>,
+    #[serde(default)]
+    memory: Vec<Memory>,
+    deprecated: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Debug {
+    #[serde(rename = "@svd")]
+    svd: Option<String>,
+}
+
+/// Processor core of the device
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Processor {
+    #[serde(rename = "@Dcore")]
+    core: Option<String>,
+    #[serde(rename = "@Dendian")]
+    endian: Option<String>,
+    #[serde(rename = "@Dmpu")]
+    mpu: Option<String>,
+    #[serde(rename = "@Dfpu")]
+    fpu: Option<String>,
+    #[serde(rename = "@Dclock")]
+    clock: Option<u64>,
+}
+
+impl Processor {
+    /// Core name, eg. `Cortex-M7`
+    pub fn core(&self) -> Option<&str> {
+        self.core.as_deref()
+    }
+
+    pub fn endian(&self) -> Option<&str> {
+        self.endian.as_deref()
+    }
+
+    pub fn mpu(&self) -> Option<&str> {
+        self.mpu.as_deref()
+    }
+
+    pub fn fpu(&self) -> Option<&str> {
+        self.fpu.as_deref()
+    }
+
+    /// Maximum clock frequency in Hz
+    pub fn clock(&self) -> Option<u64> {
+        self.clock
+    }
+}
+
+/// Reads decimal or `0x` prefixed hexadecimal number
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let text = text.trim();
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| serde::de::Error::custom(format!("invalid number {}", text)))
+}
+
+/// Memory region of the device
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Memory {
+    #[serde(rename = "@name")]
+    name: Option<String>,
+    #[serde(rename = "@id")]
+    id: Option<String>,
+    #[serde(rename = "@access")]
+    access: Option<String>,
+    #[serde(rename = "@start", deserialize_with = "number")]
+    start: u64,
+    #[serde(rename = "@size", deserialize_with = "number")]
+    size: u64,
+    #[serde(rename = "@default", default)]
+    default: Option<String>,
+    #[serde(rename = "@startup", default)]
+    startup: Option<String>,
+}
+
+fn is_set(flag: &Option<String>) -> bool {
+    matches!(flag.as_deref(), Some("1") | Some("true"))
+}
+
+impl Memory {
+    /// Name of the region, older packs use the `id` attribute, eg. `IROM1`
+    pub fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.id.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Access rights, eg. `rx`, only given by `name` based regions
+    pub fn access(&self) -> Option<&str> {
+        self.access.as_deref()
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Region is used by default by the tools
+    pub fn is_default(&self) -> bool {
+        is_set(&self.default)
+    }
+
+    /// Region holds the code executed after reset
+    pub fn is_startup(&self) -> bool {
+        is_set(&self.startup)
+    }
+}
+
+/// Device as described by the pack, with properties inherited from its family
+#[derive(Debug, Clone)]
+pub struct DeviceDescription {
+    name: String,
+    family: String,
+    sub_family: Option<String>,
+    processor: Option<Processor>,
+    svd: Option<String>,
+    memories: Vec<Memory>,
+    deprecated: bool,
+}
+
+impl DeviceDescription {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn sub_family(&self) -> Option<&str> {
+        self.sub_family.as_deref()
+    }
+
+    pub fn processor(&self) -> Option<&Processor> {
+        self.processor.as_ref()
+    }
+
+    /// Path of the device's SVD inside the ATPACK
+    pub fn svd(&self) -> Option<&str> {
+        self.svd.as_deref()
+    }
+
+    pub fn memories(&self) -> &[Memory] {
+        &self.memories
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+}
+
+/// Disagreement between the repository's listing of a pack and its descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// Descriptor's newest release isn't the listed version
+    Version { listed: String, described: String },
+    /// Listed chips the descriptor doesn't know about
+    UnknownChips(Vec<String>),
+    /// Described chips the listing omits
+    UnlistedChips(Vec<String>),
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::Version { listed, described } => write!(
+                f,
+                "listed as version {}, but its descriptor says {}",
+                listed, described
+            ),
+            Discrepancy::UnknownChips(chips) => write!(
+                f,
+                "listed chips missing in its descriptor: {}",
+                chips.join(", ")
+            ),
+            Discrepancy::UnlistedChips(chips) => write!(
+                f,
+                "described chips missing in the listing: {}",
+                chips.join(", ")
+            ),
+        }
+    }
+}
+
+/// Resolves device's properties, walking the hierarchy from the device up to its family
+fn describe(
+    family: &DeviceFamily,
+    sub_family: Option<&DeviceSubFamily>,
+    device: &Device,
+) -> DeviceDescription {
+    let mut nearest = vec![properties!(device)];
+    nearest.extend(sub_family.map(|s| properties!(s)));
+    nearest.push(properties!(family));
+
+    let mut processor = None;
+    let mut svd = None;
+    let mut memories = Vec::<Memory>::new();
+    for properties in nearest {
+        processor = processor.or_else(|| properties.processor.first().cloned());
+        svd = svd.or_else(|| properties.debug.iter().find_map(|d| d.svd.clone()));
+        for memory in properties.memory.iter() {
+            if !memories.iter().any(|m| m.name() == memory.name()) {
+                memories.push(memory.clone());
+            }
+        }
+    }
+
+    DeviceDescription {
+        name: device.name.clone(),
+        family: family.name.clone(),
+        sub_family: sub_family.map(|s| s.name.clone()),
+        processor,
+        svd,
+        memories,
+        deprecated: device.deprecated.is_some(),
+    }
+}
+
+impl PackDescriptor {
+    /// Reads the descriptor from the root of the ATPACK's archive, within default limits
+    pub fn from_pack(atpack: &mut (impl Read + Seek)) -> Result<PackDescriptor> {
+        PackDescriptor::from_pack_within(atpack, &ExtractionLimits::default())
+    }
+
+    /// Reads the descriptor from the root of the ATPACK's archive
+    ///
+    /// A descriptor exceeding the limits gives [`ExtractionError`](crate::ExtractionError).
+    pub fn from_pack_within(
+        atpack: &mut (impl Read + Seek),
+        limits: &ExtractionLimits,
+    ) -> Result<PackDescriptor> {
+        let mut archive = ZipArchive::new(atpack)?;
+        let name = archive
+            .file_names()
+            .find(|n| !n.contains('/') && n.ends_with(".pdsc"))
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("ATPACK doesn't contain pack descriptor"))?;
+        let descriptor = read_entry(&mut archive, &name, limits)?;
+        PackDescriptor::from_reader(descriptor.as_slice())
+    }
+
+    pub fn from_reader(reader: impl Read) -> Result<PackDescriptor> {
+        let reader = BufReader::new(reader);
+        Ok(quick_xml::de::from_reader(reader)?)
+    }
+
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Family of chips, pack's name without the `_DFP` suffix
+    pub fn family(&self) -> &str {
+        self.name.strip_suffix("_DFP").unwrap_or(&self.name)
+    }
+
+    /// Release history, the newest release comes first
+    pub fn releases(&self) -> &[Release] {
+        &self.releases.release
+    }
+
+    /// Pack's version, which is the one of its newest release
+    pub fn version(&self) -> Result<PackVersion> {
+        let release = self
+            .releases()
+            .first()
+            .ok_or_else(|| anyhow!("Pack descriptor of {} lists no releases", self.name))?;
+        PackVersion::parse(&release.version)
+    }
+
+    /// Names of chips supported by the pack, deprecated ones are omitted
+    pub fn chips(&self) -> Vec<String> {
+        self.devices()
+            .into_iter()
+            .filter(|d| !d.deprecated)
+            .map(|d| d.name)
+            .collect()
+    }
+
+    /// Names of device families declared by the pack
+    pub fn device_families(&self) -> Vec<&str> {
+        self.devices
+            .family
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect()
+    }
+
+    /// All devices of the pack, including deprecated ones, with inherited properties resolved
+    pub fn devices(&self) -> Vec<DeviceDescription> {
+        let mut devices = vec![];
+        for family in self.devices.family.iter() {
+            for device in family.device.iter() {
+                devices.push(describe(family, None, device));
+            }
+            for sub_family in family.sub_family.iter() {
+                for device in sub_family.device.iter() {
+                    devices.push(describe(family, Some(sub_family), device));
+                }
+            }
+        }
+        devices
+    }
+
+    /// Paths of SVDs inside the ATPACK, by names of non-deprecated devices
+    pub fn svd_paths(&self) -> Vec<(String, String)> {
+        self.devices()
+            .into_iter()
+            .filter(|d| !d.deprecated)
+            .filter_map(|d| d.svd.map(|svd| (d.name, svd)))
+            .collect()
+    }
+
+    /// Compares the descriptor with the version and chips the repository lists for the pack
+    ///
+    /// Chips are compared only when the listing has any.
+    pub fn cross_check(&self, version: &PackVersion, chips: &[String]) -> Vec<Discrepancy> {
+        let mut discrepancies = vec![];
+
+        match self.version() {
+            Ok(described) if described.semver() != version.semver() => {
+                discrepancies.push(Discrepancy::Version {
+                    listed: version.to_string(),
+                    described: described.to_string(),
+                })
+            }
+            Err(e) => discrepancies.push(Discrepancy::Version {
+                listed: version.to_string(),
+                described: e.to_string(),
+            }),
+            Ok(_) => {}
+        }
+
+        if !chips.is_empty() {
+            let described = self.chips();
+            let unknown = chips
+                .iter()
+                .filter(|c| !described.contains(c))
+                .cloned()
+                .collect::<Vec<String>>();
+            let unlisted = described
+                .into_iter()
+                .filter(|c| !chips.contains(c))
+                .collect::<Vec<String>>();
+            if !unknown.is_empty() {
+                discrepancies.push(Discrepancy::UnknownChips(unknown));
+            }
+            if !unlisted.is_empty() {
+                discrepancies.push(Discrepancy::UnlistedChips(unlisted));
+            }
+        }
+
+        discrepancies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use indoc::indoc;
+    use semver::Version;
+
+    use super::{Discrepancy, PackDescriptor};
+    use crate::extractor::{ExtractionError, ExtractionLimits};
+    use crate::version::PackVersion;
+
+    #[test]
+    fn check_descriptor_from_pack() {
+        let mut archive = File::open("test/data/test.atpack").expect("Test archive not opened");
+        let descriptor = PackDescriptor::from_pack(&mut archive).expect("Descriptor not read");
+
+        assert_eq!(descriptor.vendor(), "Microchip");
+        assert_eq!(descriptor.name(), "SAMV71_DFP");
+        assert_eq!(descriptor.family(), "SAMV71");
+        assert_eq!(
+            descriptor.version().unwrap().semver(),
+            &Version::new(4, 8, 113)
+        );
+        assert_eq!(descriptor.releases()[0].date(), Some("2022-04-06"));
+        assert_eq!(descriptor.device_families(), ["SAMV71"]);
+
+        let chips = descriptor.chips();
+        assert_eq!(chips.len(), 9);
+        assert!(chips.contains(&"ATSAMV71Q21B".to_owned()));
+        assert!(!chips.contains(&"ATSAMV71Q21".to_owned()));
+
+        let devices = descriptor.devices();
+        assert_eq!(devices.len(), 18);
+        let device = devices
+            .iter()
+            .find(|d| d.name() == "ATSAMV71Q21B")
+            .expect("Device not described");
+        assert!(!device.is_deprecated());
+        assert_eq!(device.family(), "SAMV71");
+        assert_eq!(device.svd(), Some("samv71b/svd/ATSAMV71Q21B.svd"));
+        let processor = device.processor().expect("Processor not described");
+        assert_eq!(processor.core(), Some("Cortex-M7"));
+        assert_eq!(processor.fpu(), Some("DP_FPU"));
+        let flash = &device.memories()[0];
+        assert_eq!(flash.name(), "IROM1");
+        assert_eq!((flash.start(), flash.size()), (0x0040_0000, 0x0020_0000));
+        assert!(flash.is_default() && flash.is_startup());
+        assert_eq!(device.memories().len(), 3);
+
+        assert_eq!(descriptor.svd_paths().len(), 9);
+    }
+
+    #[test]
+    fn descriptor_cross_check() {
+        let mut archive = File::open("test/data/test.atpack").expect("Test archive not opened");
+        let descriptor = PackDescriptor::from_pack(&mut archive).expect("Descriptor not read");
+        let version = PackVersion::parse("4.8.113").unwrap();
+        let chips = descriptor.chips();
+
+        assert!(descriptor.cross_check(&version, &chips).is_empty());
+        assert!(descriptor.cross_check(&version, &[]).is_empty());
+
+        let listed = ["ATSAMV71Q21B".to_owned(), "ATSAMV71Q22B".to_owned()];
+        let discrepancies = descriptor.cross_check(&PackVersion::parse("4.9").unwrap(), &listed);
+        assert_eq!(discrepancies.len(), 3);
+        assert_eq!(
+            discrepancies[0],
+            Discrepancy::Version {
+                listed: "4.9".to_owned(),
+                described: "4.8.113".to_owned()
+            }
+        );
+        assert_eq!(
+            discrepancies[1],
+            Discrepancy::UnknownChips(vec!["ATSAMV71Q22B".to_owned()])
+        );
+    }
+
+    #[test]
+    fn sub_family_chips_are_listed() {
+        let pdsc = indoc!(
+            r#"
+            <package>
+              <vendor>Microchip</vendor>
+              <name>SAMD21_DFP</name>
+              <releases><release version="3.6.144"/></releases>
+              <devices>
+                <family Dfamily="SAMD21">
+                  <device Dname="ATSAMD21E15A"/>
+                  <subFamily DsubFamily="SAMD21B">
+                    <device Dname="ATSAMD21E15B"/>
+                    <device Dname="ATSAMD21E16B"><deprecated>2021-06-28</deprecated></device>
+                  </subFamily>
+                  <subFamily DsubFamily="SAMD21L">
+                    <device Dname="ATSAMD21E15L"/>
+                  </subFamily>
+                </family>
+              </devices>
+            </package>
+            "#
+        );
+        let descriptor = PackDescriptor::from_reader(pdsc.as_bytes()).expect("Descriptor not read");
+
+        assert_eq!(
+            descriptor.chips(),
+            ["ATSAMD21E15A", "ATSAMD21E15B", "ATSAMD21E15L"]
+        );
+        let listed = ["ATSAMD21E15B".to_owned(), "ATSAMD21E15L".to_owned()];
+        assert_eq!(
+            descriptor.cross_check(&PackVersion::parse("3.6.144").unwrap(), &listed),
+            [Discrepancy::UnlistedChips(vec!["ATSAMD21E15A".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn oversized_descriptor_is_refused() {
+        let mut archive = File::open("test/data/test.atpack").expect("Test archive not opened");
+        let limits = ExtractionLimits {
+            max_entry_size: 1024,
+            ..ExtractionLimits::default()
+        };
+        let error = PackDescriptor::from_pack_within(&mut archive, &limits)
+            .expect_err("Oversized descriptor read");
+
+        assert!(matches!(
+            error.downcast_ref::<ExtractionError>(),
+            Some(ExtractionError::OversizedEntry { .. })
+        ));
+    }
+}
@@ -1,38 +1,442 @@
-use anyhow::Error;
-use bytes::Bytes;
-use reqwest::{Url, Client};
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use log::{debug, warn, LevelFilter};
+use reqwest::{Client, StatusCode, Url};
+use tokio::time::{sleep, timeout};
+
+use crate::cache::Cache;
+use crate::grinder::AtPack;
+use crate::indexer::PACK_INDEX;
+use crate::retry::{retry_after, RetryPolicy};
+
+/// Name of the repository's main page, in local repositories and in the cache
+const REPOSITORY_PAGE: &str = "index.html";
+
+/// Signatures of a ZIP archive with entries and of an empty one
+const ZIP_SIGNATURES: [&[u8]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+
+/// Download problems callers may want to tell apart, available through `Error::downcast_ref`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadError {
+    /// Server responded with other status than success
+    Status { address: Url, status: StatusCode },
+    /// Content isn't a ZIP archive, eg. an error page served with success status
+    NotAnArchive { address: Url, found: &'static str },
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Status { address, status } => {
+                write!(f, "Server responded to {} with {}", address, status)
+            }
+            DownloadError::NotAnArchive { address, found } => {
+                write!(
+                    f,
+                    "Content of {} is not an ATPACK archive, found {}",
+                    address, found
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// Checks the content looks like a ZIP archive before it gets to the extraction
+///
+/// Only the beginning of the content is read, it's rewound afterwards.
+fn verify_archive(address: &Url, content: &mut (impl Read + Seek)) -> Result<(), Error> {
+    let mut head = Vec::with_capacity(512);
+    content.rewind()?;
+    content.take(512).read_to_end(&mut head)?;
+    content.rewind()?;
+
+    if ZIP_SIGNATURES.iter().any(|s| head.starts_with(s)) {
+        return Ok(());
+    }
+
+    let found = match head.iter().find(|b| !b.is_ascii_whitespace()) {
+        None => "empty content",
+        Some(b'<') => "HTML or XML document",
+        Some(_) => "unrecognized data",
+    };
+    Err(DownloadError::NotAnArchive {
+        address: address.clone(),
+        found,
+    }
+    .into())
+}
+
+/// Destination of downloaded content, which starts over when the download is retried
+trait Sink: Write {
+    fn reset(&mut self) -> io::Result<()>;
+}
+
+impl Sink for Vec<u8> {
+    fn reset(&mut self) -> io::Result<()> {
+        self.clear();
+        Ok(())
+    }
+}
+
+impl Sink for File {
+    fn reset(&mut self) -> io::Result<()> {
+        self.set_len(0)?;
+        self.rewind()
+    }
+}
+
+/// Observer of downloads, notified as the response body arrives
+///
+/// A retried download is started again, so its progress starts from zero.
+pub trait DownloadProgress: Send + Sync {
+    /// Server accepted the request, the length is known when the server tells it
+    fn started(&self, _address: &Url, _length: Option<u64>) {}
+    /// Next part of the content arrived
+    fn advanced(&self, _address: &Url, _bytes: u64) {}
+    /// Whole content arrived
+    fn finished(&self, _address: &Url) {}
+    /// Download was given up
+    fn failed(&self, _address: &Url) {}
+}
+
+/// Turns URL or path of a local directory into the repository address
+///
+/// Directories get the trailing slash, so files are looked up inside them, not next to them.
+pub fn repository_address(location: &str) -> Result<Url, Error> {
+    // Single letter scheme is a drive on Windows, not an URL
+    let address = match Url::parse(location) {
+        Ok(address) if address.scheme().len() > 1 => address,
+        _ => {
+            let path = fs::canonicalize(location)
+                .map_err(|e| anyhow!("Repository {} is not accessible: {}", location, e))?;
+            return Url::from_directory_path(&path)
+                .map_err(|_| anyhow!("Repository {} is not a directory", path.display()));
+        }
+    };
+
+    if address.scheme() == "file" && !address.path().ends_with('/') {
+        if let Ok(path) = address.to_file_path() {
+            if path.is_dir() {
+                return Url::from_directory_path(&path)
+                    .map_err(|_| anyhow!("Repository {} is not a directory", path.display()));
+            }
+        }
+    }
+
+    Ok(address)
+}
+
+fn local_path(address: &Url) -> Result<PathBuf, Error> {
+    address
+        .to_file_path()
+        .map_err(|_| anyhow!("Address {} is not a local path", address))
+}
+
+/// Failed attempt of a download, which may be worth repeating
+struct Failure {
+    error: Error,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+impl Failure {
+    fn fatal(error: impl Into<Error>) -> Failure {
+        Failure {
+            error: error.into(),
+            retryable: false,
+            retry_after: None,
+        }
+    }
+
+    fn transient(error: impl Into<Error>) -> Failure {
+        Failure {
+            error: error.into(),
+            retryable: true,
+            retry_after: None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(error: reqwest::Error) -> Self {
+        let retryable = error.is_connect() || error.is_timeout() || error.is_body();
+        Failure {
+            error: error.into(),
+            retryable,
+            retry_after: None,
+        }
+    }
+}
 
 pub struct Downloader {
     address: Url,
     client: Client,
+    policy: RetryPolicy,
+    cache: Option<Cache>,
+    offline: bool,
+    progress: Option<Arc<dyn DownloadProgress>>,
+}
+
+fn build_client(policy: &RetryPolicy) -> Result<Client, Error> {
+    Ok(Client::builder()
+        .cookie_store(true)
+        .gzip(true)
+        .deflate(true)
+        .connect_timeout(policy.connect_timeout)
+        .connection_verbose(log::max_level() >= LevelFilter::Trace)
+        .build()?)
 }
 
 impl Downloader {
     pub fn new(address: Url) -> Result<Downloader, Error> {
-        let client = Client::builder()
-            .cookie_store(true)
-            .gzip(true)
-            .deflate(true)
-            .connection_verbose(false) // TODO: handle if high enough -v is passed
-            .build()?;
+        let policy = RetryPolicy::default();
         Ok(Downloader {
             address,
-            client,
+            client: build_client(&policy)?,
+            policy,
+            cache: None,
+            offline: false,
+            progress: None,
         })
     }
-    
+
+    /// Replaces default timeouts and retries
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Result<Self, Error> {
+        self.client = build_client(&policy)?;
+        self.policy = policy;
+        Ok(self)
+    }
+
+    pub fn address(&self) -> &Url {
+        &self.address
+    }
+
+    /// Tells the repository is a directory on the local file system
+    pub fn is_local(&self) -> bool {
+        self.address.scheme() == "file"
+    }
+
+    /// Keeps downloaded pages and archives in the cache and reuses them when present
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Serves everything from the cache, without touching the network
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Reports progress of downloads to the observer
+    pub fn progress(mut self, progress: Arc<dyn DownloadProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    async fn load_page(&self, address: Url, page: &str) -> Result<String, Error> {
+        if self.is_local() {
+            return Ok(fs::read_to_string(local_path(&address)?)?);
+        }
+
+        if self.offline {
+            return self
+                .cache
+                .as_ref()
+                .map(|c| c.load_page(&self.address, page))
+                .transpose()?
+                .flatten()
+                .ok_or_else(|| anyhow!("Page {} of {} is not cached", page, self.address));
+        }
+
+        let mut content = vec![];
+        self.fetch(address, &mut content).await?;
+        let text = String::from_utf8_lossy(&content).into_owned();
+        if let Some(ref cache) = self.cache {
+            cache.store_page(&self.address, page, &text)?;
+        }
+        Ok(text)
+    }
+
     /// Loads repository's main page for further processing
     pub async fn load_repository(&self) -> Result<String, Error> {
-        let response = self.client.get(self.address.clone())
-            .send().await?;
-        let text = response.text().await?;
-        Ok(text)
+        let address = if self.is_local() && self.address.path().ends_with('/') {
+            self.address.join(REPOSITORY_PAGE)?
+        } else {
+            self.address.clone()
+        };
+        self.load_page(address, REPOSITORY_PAGE).await
+    }
+
+    /// Loads repository's CMSIS pack index
+    pub async fn load_index(&self) -> Result<String, Error> {
+        let address = self.address.join(PACK_INDEX)?;
+        self.load_page(address, PACK_INDEX).await
     }
 
-    pub async fn load_file(&self, file: &str) -> Result<Bytes, Error> {
-        let address = self.address.join(file)?;
-        let response = self.client.get(address).send().await?;
-        let content = response.bytes().await?;
-        Ok(content)
+    /// Loads ATPACK's archive, from the cache if it's already there
+    ///
+    /// The archive is streamed to a file, either in the cache or a temporary one, so memory
+    /// use doesn't depend on its size. Local repositories are read directly, there's no point
+    /// in caching them. Content which isn't a ZIP archive is rejected and never gets to the cache.
+    pub async fn load_pack(&self, pack: &AtPack) -> Result<File, Error> {
+        let address = self.address.join(pack.archive())?;
+
+        if self.is_local() {
+            let mut file = File::open(local_path(&address)?)?;
+            verify_archive(&address, &mut file)?;
+            return Ok(file);
+        }
+
+        if let Some(ref cache) = self.cache {
+            if let Some(file) = cache.load_pack(pack)? {
+                debug!("Using cached {}", cache.pack_path(pack).display());
+                return Ok(file);
+            }
+        }
+
+        if self.offline {
+            return Err(anyhow!(
+                "ATPACK {} {} is not cached",
+                pack.family(),
+                pack.version()
+            ));
+        }
+
+        match self.cache {
+            Some(ref cache) => {
+                let mut temporary = cache.temporary_file()?;
+                self.fetch(address.clone(), temporary.as_file_mut()).await?;
+                verify_archive(&address, temporary.as_file_mut())?;
+                cache.persist_pack(pack, temporary)
+            }
+            None => {
+                let mut file = tempfile::tempfile()?;
+                self.fetch(address.clone(), &mut file).await?;
+                verify_archive(&address, &mut file)?;
+                Ok(file)
+            }
+        }
+    }
+
+    /// Downloads the address, repeating failed attempts according to the policy
+    async fn fetch(&self, address: Url, sink: &mut impl Sink) -> Result<(), Error> {
+        let mut attempt = 0;
+        loop {
+            match self.attempt(address.clone(), sink).await {
+                Ok(()) => {
+                    if let Some(ref progress) = self.progress {
+                        progress.finished(&address);
+                    }
+                    return Ok(());
+                }
+                Err(failure) if failure.retryable && attempt < self.policy.retries => {
+                    let delay = self.policy.retry_delay(attempt, failure.retry_after);
+                    warn!(
+                        "Download of {} failed ({}), retrying in {:.1}s",
+                        address,
+                        failure.error,
+                        delay.as_secs_f32()
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(failure) => {
+                    if let Some(ref progress) = self.progress {
+                        progress.failed(&address);
+                    }
+                    return Err(failure.error);
+                }
+            }
+        }
+    }
+
+    async fn attempt(&self, address: Url, sink: &mut impl Sink) -> Result<(), Failure> {
+        sink.reset().map_err(Failure::fatal)?;
+        debug!("Downloading {}", address);
+        let mut response = timeout(
+            self.policy.read_timeout,
+            self.client.get(address.clone()).send(),
+        )
+        .await
+        .map_err(|_| Failure::transient(anyhow!("Waiting for {} timed out", address)))??;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error = DownloadError::Status {
+                address: address.clone(),
+                status,
+            };
+            if !RetryPolicy::is_retryable(status) {
+                return Err(Failure::fatal(error));
+            }
+            return Err(Failure {
+                error: error.into(),
+                retryable: true,
+                retry_after: retry_after(response.headers()),
+            });
+        }
+
+        if let Some(ref progress) = self.progress {
+            progress.started(&address, response.content_length());
+        }
+
+        loop {
+            let chunk = timeout(self.policy.read_timeout, response.chunk())
+                .await
+                .map_err(|_| Failure::transient(anyhow!("Reading {} timed out", address)))??;
+            match chunk {
+                Some(chunk) => {
+                    sink.write_all(&chunk).map_err(Failure::fatal)?;
+                    if let Some(ref progress) = self.progress {
+                        progress.advanced(&address, chunk.len() as u64);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        sink.flush().map_err(Failure::fatal)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use reqwest::Url;
+
+    use super::{verify_archive, DownloadError};
+
+    #[test]
+    fn archive_verification() {
+        let address = Url::parse("https://packs.download.microchip.com/pack.atpack").unwrap();
+
+        let verify = |content: &[u8]| {
+            verify_archive(&address, &mut Cursor::new(content.to_vec()))
+                .map_err(|e| e.downcast::<DownloadError>().expect("Not a download error"))
+        };
+
+        assert!(verify(b"PK\x03\x04\x14\x00").is_ok());
+        assert!(verify(b"PK\x05\x06").is_ok());
+
+        let not_archive = |found| DownloadError::NotAnArchive {
+            address: address.clone(),
+            found,
+        };
+        assert_eq!(
+            verify(b"\n  <!DOCTYPE html><html>"),
+            Err(not_archive("HTML or XML document"))
+        );
+        assert_eq!(verify(b""), Err(not_archive("empty content")));
+        assert_eq!(verify(b"\x1f\x8b"), Err(not_archive("unrecognized data")));
+    }
+}
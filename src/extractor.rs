@@ -1,13 +1,16 @@
 use std::{
+    fmt::{self, Display},
     fs,
-    io::{BufReader, Read, Seek, Write},
-    path::{Path, PathBuf},
+    io::{self, BufReader, Read, Seek, Write},
+    path::Path,
     vec,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use glob::{MatchOptions, Pattern};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use zip::ZipArchive;
+use zip::{read::ZipFile, result::ZipError, ZipArchive};
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
@@ -43,54 +46,440 @@ struct Includes {
     pattern: String,
 }
 
-fn extract_svd_paths_from_compressed_manifest(manifest: &mut impl Read) -> Result<Vec<String>> {
+/// Resource type of SVD files in the manifest
+pub const SVD_RESOURCE: &str = "svd";
+
+/// Types of resources to extract, named as in the `package.content` manifest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResourceTypes {
+    /// Every resource listed in the manifest
+    All,
+    /// Resources of given types only, eg. `svd`, `atdf` or `c.header`
+    Only(Vec<String>),
+}
+
+impl Default for ResourceTypes {
+    fn default() -> Self {
+        ResourceTypes::Only(vec![SVD_RESOURCE.to_owned()])
+    }
+}
+
+impl ResourceTypes {
+    pub fn contains(&self, resource_type: &str) -> bool {
+        match self {
+            ResourceTypes::All => true,
+            ResourceTypes::Only(types) => types.iter().any(|t| t == resource_type),
+        }
+    }
+}
+
+/// File extracted from an ATPACK
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artefact {
+    resource_type: String,
+    path: String,
+}
+
+impl Artefact {
+    pub fn new(resource_type: &str, path: &str) -> Artefact {
+        Artefact {
+            resource_type: resource_type.to_owned(),
+            path: path.to_owned(),
+        }
+    }
+
+    pub fn resource_type(&self) -> &str {
+        &self.resource_type
+    }
+
+    /// Location relative to the destination directory
+    ///
+    /// SVDs are placed directly in it, other resources go to subdirectories named by their type,
+    /// keeping their location in the ATPACK, eg. `c.header/samv71b/include/sam.h`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Sizes an ATPACK's content must fit in, protecting against decompression bombs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractionLimits {
+    /// Bytes a single extracted file can take
+    pub max_entry_size: u64,
+    /// Bytes all files extracted from the ATPACK can take together
+    pub max_total_size: u64,
+    /// Highest ratio of extracted to compressed size of a file larger than [`RATIO_CHECK_SIZE`]
+    pub max_compression_ratio: u64,
+}
+
+impl Default for ExtractionLimits {
+    fn default() -> Self {
+        ExtractionLimits {
+            max_entry_size: 128 * 1024 * 1024,
+            max_total_size: 2 * 1024 * 1024 * 1024,
+            max_compression_ratio: 100,
+        }
+    }
+}
+
+/// Reasons to refuse extraction of an ATPACK's entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractionError {
+    /// Path is absolute or leads outside of the destination directory
+    UnsafePath { path: String },
+    /// Entry is a symbolic link
+    SymbolicLink { path: String },
+    /// Entry is bigger than a single file is allowed to be
+    OversizedEntry { path: String, limit: u64 },
+    /// Entry is compressed better than any genuine file is
+    SuspiciousCompression {
+        path: String,
+        ratio: u64,
+        limit: u64,
+    },
+    /// Extracted files together are bigger than allowed
+    OversizedPack { limit: u64 },
+}
+
+impl Display for ExtractionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractionError::UnsafePath { path } => write!(f, "Unsafe path {} in ATPACK", path),
+            ExtractionError::SymbolicLink { path } => {
+                write!(f, "Symbolic link {} in ATPACK", path)
+            }
+            ExtractionError::OversizedEntry { path, limit } => {
+                write!(f, "Entry {} of ATPACK exceeds {} bytes", path, limit)
+            }
+            ExtractionError::SuspiciousCompression { path, ratio, limit } => write!(
+                f,
+                "Entry {} of ATPACK is compressed {}:1, more than allowed {}:1",
+                path, ratio, limit
+            ),
+            ExtractionError::OversizedPack { limit } => {
+                write!(f, "Extracted ATPACK's content exceeds {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtractionError {}
+
+/// Normalizes path of the archive's entry, refusing absolute ones and ones with `..`
+fn sanitize_path(path: &str) -> Result<String, ExtractionError> {
+    let unsafe_path = || ExtractionError::UnsafePath {
+        path: path.to_owned(),
+    };
+    if path.starts_with('/') || path.contains(['\\', ':', '\0']) {
+        return Err(unsafe_path());
+    }
+
+    let mut components = Vec::<&str>::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(unsafe_path()),
+            component => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return Err(unsafe_path());
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Resource type names a subdirectory, it must be a single plain component
+fn sanitize_resource_type(resource_type: &str) -> Result<&str, ExtractionError> {
+    match sanitize_path(resource_type) {
+        Ok(sanitized) if sanitized == resource_type && !sanitized.contains('/') => {
+            Ok(resource_type)
+        }
+        _ => Err(ExtractionError::UnsafePath {
+            path: resource_type.to_owned(),
+        }),
+    }
+}
+
+fn is_symbolic_link(mode: Option<u32>) -> bool {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+    mode.is_some_and(|m| m & S_IFMT == S_IFLNK)
+}
+
+/// Entries smaller than this aren't checked for their compression ratio, small text compresses well
+pub const RATIO_CHECK_SIZE: u64 = 1024 * 1024;
+
+/// Refuses the entry by what the archive declares about it
+fn check_entry(
+    entry: &ZipFile,
+    path: &str,
+    limits: &ExtractionLimits,
+) -> Result<(), ExtractionError> {
+    if is_symbolic_link(entry.unix_mode()) {
+        return Err(ExtractionError::SymbolicLink {
+            path: path.to_owned(),
+        });
+    }
+    if entry.size() > limits.max_entry_size {
+        return Err(ExtractionError::OversizedEntry {
+            path: path.to_owned(),
+            limit: limits.max_entry_size,
+        });
+    }
+    let ratio = entry.size() / entry.compressed_size().max(1);
+    if entry.size() > RATIO_CHECK_SIZE && ratio > limits.max_compression_ratio {
+        return Err(ExtractionError::SuspiciousCompression {
+            path: path.to_owned(),
+            ratio,
+            limit: limits.max_compression_ratio,
+        });
+    }
+    Ok(())
+}
+
+/// Copies the entry, accepting at most `available` bytes of what's left from the pack's limit
+///
+/// Sizes declared in the archive can lie, so the limits are enforced while decompressing.
+fn copy_entry(
+    entry: &mut ZipFile,
+    output: &mut impl Write,
+    path: &str,
+    limits: &ExtractionLimits,
+    available: u64,
+) -> Result<u64> {
+    let compressed = entry.compressed_size().max(1);
+    let ratio_limit = limits
+        .max_compression_ratio
+        .saturating_mul(compressed)
+        .max(RATIO_CHECK_SIZE);
+    let allowed = limits.max_entry_size.min(ratio_limit).min(available);
+    let written = io::copy(&mut entry.take(allowed.saturating_add(1)), output)?;
+    if written <= allowed {
+        return Ok(written);
+    }
+
+    Err(if written > limits.max_entry_size {
+        ExtractionError::OversizedEntry {
+            path: path.to_owned(),
+            limit: limits.max_entry_size,
+        }
+    } else if written > ratio_limit {
+        ExtractionError::SuspiciousCompression {
+            path: path.to_owned(),
+            ratio: written / compressed,
+            limit: limits.max_compression_ratio,
+        }
+    } else {
+        ExtractionError::OversizedPack {
+            limit: limits.max_total_size,
+        }
+    }
+    .into())
+}
+
+/// Reads the whole entry to memory, within the limits
+pub(crate) fn read_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+    limits: &ExtractionLimits,
+) -> Result<Vec<u8>> {
+    let mut entry = archive.by_name(name)?;
+    check_entry(&entry, name, limits)?;
+    let mut content = Vec::new();
+    copy_entry(&mut entry, &mut content, name, limits, limits.max_total_size)?;
+    Ok(content)
+}
+
+/// Fails on unsafe path of an SVD, other resources aren't essential and are skipped
+fn refuse_unsafe_path(resource_type: &str, error: ExtractionError) -> Result<(), ExtractionError> {
+    if resource_type == SVD_RESOURCE {
+        return Err(error);
+    }
+    warn!("{}, skipping it", error);
+    Ok(())
+}
+
+/// Lists `(type, pattern)` of requested resources in the archive, each pattern only once
+///
+/// Patterns are paths relative to the root of the archive, they may contain wildcards.
+fn extract_resource_paths_from_compressed_manifest(
+    manifest: &mut impl Read,
+    types: &ResourceTypes,
+) -> Result<Vec<(String, String)>> {
     let reader = BufReader::new(manifest);
     let package: Package = quick_xml::de::from_reader(reader)?;
 
-    let mut svds_paths = Vec::<String>::new();
+    let mut paths = Vec::<(String, String)>::new();
     package.content.resources.iter().for_each(|e| {
         e.resource.iter().for_each(|r| {
-            if r.r#type == "svd" {
-                if let Some(i) = r.includes.first() {
-                    svds_paths.push([r.subdir.clone(), i.pattern.clone()].join("/"));
-                }
+            if types.contains(&r.r#type) {
+                r.includes.iter().for_each(|i| {
+                    let path = (
+                        r.r#type.clone(),
+                        [r.subdir.clone(), i.pattern.clone()].join("/"),
+                    );
+                    if !paths.contains(&path) {
+                        paths.push(path);
+                    }
+                })
             }
         })
     });
 
-    Ok(svds_paths)
+    Ok(paths)
 }
 
-pub fn extract_svds_from_pack(
-    atpack: &mut (impl Read + Seek),
-    destination: &Path,
-) -> Result<Vec<String>> {
-    let mut archive = ZipArchive::new(atpack)?;
-    let mut manifest = archive.by_name("package.content")?;
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
 
-    let svds_paths = extract_svd_paths_from_compressed_manifest(&mut manifest)?;
+/// Replaces wildcard patterns with matching entries of the archive, literal paths are kept as they are
+fn expand_resource_patterns(
+    patterns: Vec<(String, String)>,
+    entries: &[&str],
+) -> Result<Vec<(String, String)>> {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
 
-    drop(manifest);
+    let mut paths = Vec::<(String, String)>::new();
+    for (resource_type, pattern) in patterns {
+        if !is_glob(&pattern) {
+            if !paths.contains(&(resource_type.clone(), pattern.clone())) {
+                paths.push((resource_type, pattern));
+            }
+            continue;
+        }
 
-    let mut successful_svds: Vec<String> = vec![];
+        let glob = Pattern::new(&pattern)
+            .map_err(|e| anyhow!("Invalid resource pattern {}: {}", pattern, e))?;
+        let mut matched = entries
+            .iter()
+            .filter(|e| !e.ends_with('/') && glob.matches_with(e, options))
+            .collect::<Vec<_>>();
+        matched.sort();
+        if matched.is_empty() {
+            warn!(
+                "Resource pattern {} doesn't match anything in the archive",
+                pattern
+            );
+        }
+        for entry in matched {
+            if sanitize_path(entry).ok().as_deref() != Some(*entry) {
+                let path = entry.to_string();
+                refuse_unsafe_path(&resource_type, ExtractionError::UnsafePath { path })?;
+                continue;
+            }
+            let path = (resource_type.clone(), entry.to_string());
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
 
-    for svd_path in svds_paths {
-        let mut svd = archive.by_name(&svd_path)?;
-        let mut content = String::with_capacity(1000000);
-        svd.read_to_string(&mut content)?;
+    Ok(paths)
+}
+
+pub fn extract_svds_from_pack(
+    atpack: &mut (impl Read + Seek),
+    destination: &Path,
+) -> Result<Vec<String>> {
+    let artefacts = extract_resources_from_pack(
+        atpack,
+        destination,
+        &ResourceTypes::default(),
+        &ExtractionLimits::default(),
+    )?;
+    Ok(artefacts.into_iter().map(|a| a.path).collect())
+}
 
-        let svd_path = PathBuf::from(svd_path);
-        let filename = svd_path.file_name().unwrap(); // TODO: to error if not present
-        fs::create_dir_all(destination)?;
-        let path = destination.join(&filename);
+/// Extracts resources of requested types listed in the ATPACK's `package.content` manifest
+///
+/// ATPACKs come from the internet, so entries leading outside of the destination, symbolic
+/// links and entries exceeding the limits are refused with `ExtractionError`.
+pub fn extract_resources_from_pack(
+    atpack: &mut (impl Read + Seek),
+    destination: &Path,
+    types: &ResourceTypes,
+    limits: &ExtractionLimits,
+) -> Result<Vec<Artefact>> {
+    let mut archive = ZipArchive::new(atpack)?;
+    let manifest = read_entry(&mut archive, "package.content", limits)?;
+
+    let mut resource_patterns = vec![];
+    for (resource_type, pattern) in
+        extract_resource_paths_from_compressed_manifest(&mut manifest.as_slice(), types)?
+    {
+        sanitize_resource_type(&resource_type)?;
+        match sanitize_path(&pattern) {
+            Ok(pattern) => resource_patterns.push((resource_type, pattern)),
+            // Manifests refer to shared files like `samv71b/../scripts/samba.py`
+            Err(e) => refuse_unsafe_path(&resource_type, e)?,
+        }
+    }
 
-        let mut file = fs::File::create(path)?; // TODO: extract file name only
-        file.write_all(&content.as_bytes())?;
+    let entries = archive.file_names().collect::<Vec<&str>>();
+    let resource_paths = expand_resource_patterns(resource_patterns, &entries)?;
+
+    let mut artefacts: Vec<Artefact> = vec![];
+    let mut total: u64 = 0;
+
+    for (resource_type, resource_path) in resource_paths {
+        let mut resource = match archive.by_name(&resource_path) {
+            Ok(resource) => resource,
+            // Manifests list files some ATPACKs don't ship, only SVDs are essential
+            Err(ZipError::FileNotFound) if resource_type != SVD_RESOURCE => {
+                warn!(
+                    "Resource {} listed in the manifest is missing, skipping it",
+                    resource_path
+                );
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if resource.is_dir() {
+            continue;
+        }
+        check_entry(&resource, &resource_path, limits)?;
+
+        let relative = if resource_type == SVD_RESOURCE {
+            // Sanitized path is never empty, nor ends with `..`
+            resource_path
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        } else {
+            format!("{}/{}", resource_type, resource_path)
+        };
+        let path = destination.join(&relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        debug!("Extracting {} to {}", resource_path, path.display());
+        let mut file = fs::File::create(&path)?;
+        let available = limits.max_total_size.saturating_sub(total);
+        let written = match copy_entry(&mut resource, &mut file, &resource_path, limits, available)
+        {
+            Ok(written) => written,
+            Err(e) => {
+                drop(file);
+                fs::remove_file(&path)?;
+                return Err(e);
+            }
+        };
+        total += written;
 
-        successful_svds.push(filename.to_string_lossy().to_string());
+        artefacts.push(Artefact {
+            resource_type,
+            path: relative,
+        });
     }
 
-    Ok(successful_svds)
+    Ok(artefacts)
 }
 
 #[cfg(test)]
@@ -98,12 +487,16 @@ mod test {
     use std::{
         ffi::OsStr,
         fs::{self, File},
-        io::Error as IoError,
+        io::{Cursor, Error as IoError, Write},
     };
 
-    use super::{Content, Includes, Package, Resource, Resources};
+    use super::{
+        Content, ExtractionError, ExtractionLimits, Includes, Package, Resource, ResourceTypes,
+        Resources,
+    };
     use indoc::indoc;
     use tempfile::TempDir;
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
     #[test]
     fn try_serialize() {
@@ -192,7 +585,7 @@ mod test {
         "#
         );
         let package: Package =
-            quick_xml::de::from_str(&PACKAGE_CONTENT_MANIFEST).expect("Shall deserialize");
+            quick_xml::de::from_str(PACKAGE_CONTENT_MANIFEST).expect("Shall deserialize");
 
         let mut found = false;
         package.content.resources.iter().for_each(|e| {
@@ -213,8 +606,15 @@ mod test {
     #[test]
     fn check_svd_paths_extraction() {
         let mut f = File::open("test/data/package.content").expect("Test file not opened");
-        let svds_paths =
-            super::extract_svd_paths_from_compressed_manifest(&mut f).expect("Extraction failed");
+        let paths = super::extract_resource_paths_from_compressed_manifest(
+            &mut f,
+            &ResourceTypes::default(),
+        )
+        .expect("Extraction failed");
+        let svds_paths = paths
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect::<Vec<String>>();
 
         println!("{:?}", svds_paths);
 
@@ -268,4 +668,275 @@ mod test {
             .iter()
             .any(|f| f.file_name() == Some(OsStr::new("ATSAMV71N20B.svd"))));
     }
+
+    #[test]
+    fn check_resources_extraction() {
+        let tempdir =
+            TempDir::with_prefix("atpack-resources").expect("Temporary directory creation failed");
+        let mut archive = File::open("test/data/test.atpack").expect("Test archive not opened");
+        let types = ResourceTypes::Only(vec![
+            "svd".to_owned(),
+            "gcc.linker".to_owned(),
+            "atdf".to_owned(),
+        ]);
+        let artefacts = super::extract_resources_from_pack(
+            &mut archive,
+            tempdir.path(),
+            &types,
+            &ExtractionLimits::default(),
+        )
+        .expect("Extraction failed");
+
+        assert_eq!(
+            artefacts
+                .iter()
+                .filter(|a| a.resource_type() == "svd")
+                .count(),
+            9
+        );
+        assert_eq!(
+            artefacts
+                .iter()
+                .filter(|a| a.resource_type() == "gcc.linker")
+                .count(),
+            18
+        );
+        // Not present in the test archive, despite being listed
+        assert_eq!(
+            artefacts
+                .iter()
+                .filter(|a| a.resource_type() == "atdf")
+                .count(),
+            0
+        );
+        assert!(artefacts.iter().any(|a| a.path() == "ATSAMV71Q21B.svd"));
+        assert!(artefacts
+            .iter()
+            .any(|a| a.path() == "gcc.linker/samv71b/gcc/gcc/samv71q21b_flash.ld"));
+        assert!(tempdir.path().join("ATSAMV71Q21B.svd").is_file());
+        assert!(tempdir
+            .path()
+            .join("gcc.linker/samv71b/gcc/gcc/samv71q21b_flash.ld")
+            .is_file());
+        assert!(tempdir
+            .path()
+            .join("gcc.linker/samv71b/gcc/gcc/samv71q21b_sram.ld")
+            .is_file());
+    }
+
+    #[test]
+    fn check_resource_patterns_expansion() {
+        let entries = [
+            "samv71b/",
+            "samv71b/gcc/",
+            "samv71b/gcc/gcc/samv71q21b_flash.ld",
+            "samv71b/gcc/gcc/samv71q21b_sram.ld",
+            "samv71b/include/sam.h",
+            "samv71b/include/component/acc.h",
+        ];
+        let patterns = vec![
+            ("gcc.linker".to_owned(), "samv71b/gcc/gcc/*.ld".to_owned()),
+            ("c.header".to_owned(), "samv71b/include/*.h".to_owned()),
+            ("c.header".to_owned(), "samv71b/include/**/*.h".to_owned()),
+            ("c.header".to_owned(), "samv71b/include/sam.h".to_owned()),
+            ("atdf".to_owned(), "samv71b/atdf/*.atdf".to_owned()),
+        ];
+
+        let paths = super::expand_resource_patterns(patterns, &entries).expect("Expansion failed");
+
+        assert_eq!(
+            paths,
+            [
+                (
+                    "gcc.linker".to_owned(),
+                    "samv71b/gcc/gcc/samv71q21b_flash.ld".to_owned()
+                ),
+                (
+                    "gcc.linker".to_owned(),
+                    "samv71b/gcc/gcc/samv71q21b_sram.ld".to_owned()
+                ),
+                ("c.header".to_owned(), "samv71b/include/sam.h".to_owned()),
+                (
+                    "c.header".to_owned(),
+                    "samv71b/include/component/acc.h".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_path_sanitization() {
+        assert_eq!(
+            super::sanitize_path("samv71b/svd/ATSAMV71Q21B.svd").unwrap(),
+            "samv71b/svd/ATSAMV71Q21B.svd"
+        );
+        assert_eq!(
+            super::sanitize_path("./samv71b//svd/").unwrap(),
+            "samv71b/svd"
+        );
+
+        for path in [
+            "/etc/passwd",
+            "../evil.svd",
+            "samv71b/../scripts/samba.py",
+            "samv71b/../../evil.svd",
+            "C:/evil.svd",
+            "samv71b\\..\\..\\evil.svd",
+            "samv71b/..",
+            "",
+        ] {
+            assert_eq!(
+                super::sanitize_path(path),
+                Err(ExtractionError::UnsafePath {
+                    path: path.to_owned()
+                })
+            );
+        }
+    }
+
+    /// ATPACK with a manifest listing a single SVD resource, made by `write` entries
+    fn hostile_pack(
+        pattern: &str,
+        write: impl FnOnce(&mut ZipWriter<Cursor<Vec<u8>>>),
+    ) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("package.content", FileOptions::default())
+            .unwrap();
+        write!(
+            zip,
+            r#"<package><content><resources target="ATSAMV71Q21B"><resource type="svd" subdir="samv71b/svd"><includes pattern="{}"/></resource></resources></content></package>"#,
+            pattern
+        )
+        .unwrap();
+        write(&mut zip);
+        zip.finish().unwrap()
+    }
+
+    fn extract(pack: &mut Cursor<Vec<u8>>, limits: &ExtractionLimits) -> Option<ExtractionError> {
+        let tempdir =
+            TempDir::with_prefix("atpack-hostile").expect("Temporary directory creation failed");
+        let result = super::extract_resources_from_pack(
+            pack,
+            tempdir.path(),
+            &ResourceTypes::default(),
+            limits,
+        );
+        result.err().map(|e| {
+            e.downcast::<ExtractionError>()
+                .expect("Not an extraction error")
+        })
+    }
+
+    #[test]
+    fn hostile_entries_are_refused() {
+        let limits = ExtractionLimits::default();
+
+        let mut pack = hostile_pack("../../../evil.svd", |_| {});
+        assert!(matches!(
+            extract(&mut pack, &limits),
+            Some(ExtractionError::UnsafePath { .. })
+        ));
+
+        let mut pack = hostile_pack("**/*.svd", |zip| {
+            zip.start_file("samv71b/svd/../../../evil.svd", FileOptions::default())
+                .unwrap();
+        });
+        assert!(matches!(
+            extract(&mut pack, &limits),
+            Some(ExtractionError::UnsafePath { .. })
+        ));
+
+        let mut pack = hostile_pack("ATSAMV71Q21B.svd", |zip| {
+            zip.add_symlink(
+                "samv71b/svd/ATSAMV71Q21B.svd",
+                "/etc/passwd",
+                FileOptions::default(),
+            )
+            .unwrap();
+        });
+        assert!(matches!(
+            extract(&mut pack, &limits),
+            Some(ExtractionError::SymbolicLink { .. })
+        ));
+
+        let mut pack = hostile_pack("ATSAMV71Q21B.svd", |zip| {
+            zip.start_file("samv71b/svd/ATSAMV71Q21B.svd", FileOptions::default())
+                .unwrap();
+            zip.write_all(&vec![0u8; 2 * 1024 * 1024]).unwrap();
+        });
+        assert!(matches!(
+            extract(&mut pack, &limits),
+            Some(ExtractionError::SuspiciousCompression { .. })
+        ));
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("package.content", FileOptions::default())
+            .unwrap();
+        zip.write_all(&vec![b' '; 2 * 1024 * 1024]).unwrap();
+        let mut pack = zip.finish().unwrap();
+        assert!(matches!(
+            extract(&mut pack, &limits),
+            Some(ExtractionError::SuspiciousCompression { .. })
+        ));
+
+        let limits = ExtractionLimits {
+            max_entry_size: 1024,
+            ..ExtractionLimits::default()
+        };
+        let mut pack = hostile_pack("ATSAMV71Q21B.svd", |zip| {
+            let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+            zip.start_file("samv71b/svd/ATSAMV71Q21B.svd", options)
+                .unwrap();
+            zip.write_all(&[b'x'; 2048]).unwrap();
+        });
+        assert!(matches!(
+            extract(&mut pack, &limits),
+            Some(ExtractionError::OversizedEntry { .. })
+        ));
+        pack.set_position(0);
+        assert_eq!(extract(&mut pack, &ExtractionLimits::default()), None);
+    }
+
+    #[test]
+    fn small_compressible_entries_are_accepted() {
+        let mut pack = hostile_pack("ATSAMV71Q21B.svd", |zip| {
+            zip.start_file("samv71b/svd/ATSAMV71Q21B.svd", FileOptions::default())
+                .unwrap();
+            zip.write_all(&vec![b' '; 512 * 1024]).unwrap();
+        });
+        assert_eq!(extract(&mut pack, &ExtractionLimits::default()), None);
+    }
+
+    #[test]
+    fn unsafe_resources_are_skipped() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("package.content", FileOptions::default())
+            .unwrap();
+        write!(
+            zip,
+            r#"<package><content><resources target="ATSAMV71Q21B"><resource type="svd" subdir="samv71b/svd"><includes pattern="ATSAMV71Q21B.svd"/></resource><resource type="atdf" subdir="samv71b/atdf"><includes pattern="**/*.atdf"/><includes pattern="../../evil.atdf"/></resource></resources></content></package>"#
+        )
+        .unwrap();
+        for entry in [
+            "samv71b/svd/ATSAMV71Q21B.svd",
+            "samv71b/atdf/ATSAMV71Q21B.atdf",
+            "samv71b/atdf/../../../evil.atdf",
+        ] {
+            zip.start_file(entry, FileOptions::default()).unwrap();
+        }
+        let mut pack = zip.finish().unwrap();
+
+        let tempdir =
+            TempDir::with_prefix("atpack-hostile").expect("Temporary directory creation failed");
+        let artefacts = super::extract_resources_from_pack(
+            &mut pack,
+            tempdir.path(),
+            &ResourceTypes::All,
+            &ExtractionLimits::default(),
+        )
+        .expect("Extraction failed");
+
+        let paths = artefacts.iter().map(|a| a.path()).collect::<Vec<&str>>();
+        assert_eq!(paths, ["ATSAMV71Q21B.svd", "atdf/samv71b/atdf/ATSAMV71Q21B.atdf"]);
+    }
 }
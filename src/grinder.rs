@@ -1,23 +1,111 @@
-use std::{rc::Rc};
+use std::{
+    fmt::{self, Display},
+    sync::Arc,
+};
 
 use anyhow::Error;
-use lazy_regex::{regex_captures};
-use semver::Version;
-use strum::VariantNames;
+use lazy_regex::{regex_captures, regex_is_match};
+use log::{debug, info, warn};
+use scraper::{ElementRef, Html, Selector};
+use semver::VersionReq;
+use serde::Serialize;
 use std::clone::Clone;
-use scraper::{Html, Selector};
+
+use crate::version::PackVersion;
+
+/// Tells whether the name belongs to a family of ARM based chips, which come with SVDs
+pub(crate) fn is_family_name(name: &str) -> bool {
+    regex_is_match!("^(SAM|PIC32C)[A-Z0-9-]+$", name)
+}
 
 pub struct Grinder {
     document: Html,
+    strict: bool,
+}
+
+/// Malformed markup of the repository's page, available through `Error::downcast_ref`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrinderError {
+    /// Panel, counted from one, has no title to take the family from
+    MissingTitle { panel: usize },
+    /// Release row lacks the description column
+    MissingColumn { family: String, row: String },
+    /// Release row lacks the download button
+    MissingDownloadButton { family: String, row: String },
+    /// Download button doesn't tell where the archive is
+    MissingDownloadLink { family: String, row: String },
+    /// Release row doesn't start with a valid version
+    InvalidVersion {
+        family: String,
+        row: String,
+        reason: String,
+    },
 }
 
+impl Display for GrinderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrinderError::MissingTitle { panel } => write!(f, "Panel {} has no title", panel),
+            GrinderError::MissingColumn { family, row } => {
+                write!(f, "Release of {} lacks description column: {}", family, row)
+            }
+            GrinderError::MissingDownloadButton { family, row } => {
+                write!(f, "Release of {} lacks download button: {}", family, row)
+            }
+            GrinderError::MissingDownloadLink { family, row } => {
+                write!(f, "Release of {} lacks download link: {}", family, row)
+            }
+            GrinderError::InvalidVersion {
+                family,
+                row,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "Release of {} has invalid version ({}): {}",
+                    family, reason, row
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GrinderError {}
+
+#[derive(Debug, Serialize)]
 pub struct AtPacksCollection {
-    family: Rc<String>,
-    chips: Rc<Vec<String>>,
+    family: Arc<String>,
+    chips: Arc<Vec<String>>,
     packs: Vec<AtPack>
 }
 
 impl AtPacksCollection {
+    pub(crate) fn new(family: Arc<String>, chips: Arc<Vec<String>>, packs: Vec<AtPack>) -> Self {
+        AtPacksCollection {
+            family,
+            chips,
+            packs,
+        }
+    }
+
+    /// Adds release of the family's ATPACK, sharing the family and chips list
+    pub(crate) fn add_pack(&mut self, version: PackVersion, archive: String) -> &mut AtPack {
+        self.packs.push(AtPack {
+            family: self.family.clone(),
+            version,
+            chips: self.chips.clone(),
+            archive,
+            date: None,
+            description: None,
+        });
+        self.packs.last_mut().unwrap()
+    }
+
+    /// Orders packs from the newest to the oldest
+    pub(crate) fn sort_packs(&mut self) {
+        self.packs.sort_by(|a, b| b.version.cmp(&a.version));
+    }
+
     pub fn family(&self) -> &str {
         &self.family
     }
@@ -29,21 +117,47 @@ impl AtPacksCollection {
     pub fn packs(&self) -> &[AtPack] {
         &self.packs
     }
+
+    /// Returns the ATPACK with the highest version, regardless of the order on the page
+    pub fn newest(&self) -> Option<&AtPack> {
+        self.packs.iter().max_by(|a, b| a.version.cmp(&b.version))
+    }
+
+    /// Returns the ATPACK with the highest version matching the requirement
+    pub fn select(&self, requirement: &VersionReq) -> Option<&AtPack> {
+        self.packs
+            .iter()
+            .filter(|p| requirement.matches(p.version.semver()))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
 }
 
+#[derive(Debug, Serialize)]
 pub struct AtPack {
-    family: Rc<String>,
-    version: Version,
-    chips: Rc<Vec<String>>,
+    #[serde(skip)]
+    family: Arc<String>,
+    version: PackVersion,
+    #[serde(skip)]
+    chips: Arc<Vec<String>>,
     archive: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
 }
 
 impl AtPack {
+    /// Sets when the pack was released and what changed in it
+    pub(crate) fn set_release(&mut self, date: Option<String>, description: Option<String>) {
+        self.date = date;
+        self.description = description;
+    }
+
     pub fn family(&self) -> &str {
         &self.family
     }
 
-    pub fn version(&self) -> &Version {
+    pub fn version(&self) -> &PackVersion {
         &self.version
     }
 
@@ -54,79 +168,269 @@ impl AtPack {
     pub fn archive(&self) -> &str {
         &self.archive
     }
+
+    /// Release date as published, eg. `2022-04-06`
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    /// Release notes, what changed in the pack
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
 }
 
 impl Grinder {
     pub fn new(input: &str) -> Grinder {
         Grinder {
             document: Html::parse_document(input),
+            strict: false,
+        }
+    }
+
+    /// Fails on malformed markup instead of skipping it with a warning
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Reports the problem as a warning, or turns it into failure in strict mode
+    fn tolerate(&self, error: GrinderError) -> Result<(), GrinderError> {
+        if self.strict {
+            return Err(error);
         }
+        warn!("{}, skipping it", error);
+        Ok(())
+    }
+
+    fn process_release(
+        &self,
+        family: &Arc<String>,
+        chips: &Arc<Vec<String>>,
+        release_element: ElementRef,
+    ) -> Result<Option<AtPack>, GrinderError> {
+        let td_selector = Selector::parse("td").unwrap();
+        let download_selector = Selector::parse("td>button.download-button").unwrap();
+
+        let text = release_element.text().collect::<String>();
+        let row = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        let mut column_selector = release_element.select(&td_selector);
+
+        let Some(first_column) = column_selector.next() else {
+            return Ok(None);
+        };
+        let text: String = first_column.text().collect();
+        let version = text.split_whitespace().next().unwrap_or_default();
+        let date = regex_captures!(r"\(([^)]+)\)", &text).map(|(_, date)| date.trim().to_owned());
+        debug!("Found {} release {}", family, version);
+        let version = PackVersion::parse(version).map_err(|e| GrinderError::InvalidVersion {
+            family: family.to_string(),
+            row: row.clone(),
+            reason: e.to_string(),
+        })?;
+        let description = column_selector
+            .next()
+            .ok_or_else(|| GrinderError::MissingColumn {
+                family: family.to_string(),
+                row: row.clone(),
+            })?;
+        let download = release_element
+            .select(&download_selector)
+            .next()
+            .ok_or_else(|| GrinderError::MissingDownloadButton {
+                family: family.to_string(),
+                row: row.clone(),
+            })?;
+        let archive = download.value().attr("data-link").ok_or_else(|| {
+            GrinderError::MissingDownloadLink {
+                family: family.to_string(),
+                row,
+            }
+        })?;
+
+        let description = description.text().collect::<String>();
+        let description = description
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        Ok(Some(AtPack {
+            family: family.clone(),
+            version,
+            chips: chips.clone(),
+            archive: archive.to_string(),
+            date,
+            description: Some(description).filter(|d| !d.is_empty()),
+        }))
     }
-    
+
     pub fn process_packs(&self) -> Result<Vec<AtPacksCollection>, Error> {
         let panel_selector = Selector::parse("div.panel-group>div.panel").unwrap();
         let title_selector = Selector::parse("div.panel-heading>h3.panel-title>a").unwrap();
         let device_list_selector = Selector::parse("div.panel-body>div.device-list>ul.list-inline>li.device-list-item").unwrap();
         let release_selector = Selector::parse("div.releases>table.table>tbody>tr").unwrap();
-        let td_selector = Selector::parse("td").unwrap();
 
-        let collections = self.document.select(&panel_selector).filter_map(|panel| {
-            let title_element = panel.select(&title_selector).next().expect("Couldn't find title element");
+        let mut collections = Vec::<AtPacksCollection>::new();
+
+        for (index, panel) in self.document.select(&panel_selector).enumerate() {
+            let Some(title_element) = panel.select(&title_selector).next() else {
+                self.tolerate(GrinderError::MissingTitle { panel: index + 1 })?;
+                continue;
+            };
             let title = title_element.text().collect::<String>();
-            // dbg!("* {}", &title);
-
-            let c = regex_captures!("^Microchip (SAM[A-Z0-9-]+)", &title);
-            // take regex, and filter out r`Microchip (SAM[A-Z0-9]+)`
-            let family = if let Some((_, sam)) = c {
-                if crate::ChipsFamily::VARIANTS.contains(&sam) {
-                    Rc::new(sam.to_owned())
-                } else {
-                    return None;
-                }
-            } else {
-                return None;
+            debug!("Processing panel {}", title.trim());
+
+            // take regex, and filter out families of chips with SVDs
+            let family = match regex_captures!("^Microchip ([A-Z][A-Z0-9-]+)", &title) {
+                Some((_, name)) if is_family_name(name) => Arc::new(name.to_owned()),
+                _ => continue,
             };
 
-            println!("* Found {}", family);
+            info!("Found {} family", family);
 
             let chips = panel.select(&device_list_selector).map(|device_element| {
-                device_element.text().collect::<String>().trim().to_owned() 
+                device_element.text().collect::<String>().trim().to_owned()
             }).collect::<Vec<String>>();
-            let chips = Rc::new(chips);
-
-            let versions = panel.select(&release_selector).filter_map(|release_element| {
-                let mut column_selector = release_element.select(&td_selector);
-
-                match column_selector.next() {
-                    Some(first_column) => {
-                        let text: String = first_column.text().collect();
-                        let version = text.split(char::is_whitespace).next().unwrap(); // TODO: Do better error handling / consider release date
-                        dbg!(&version);
-                        let version = Version::parse(&version).expect("Version should not fail"); // TODO: Can we do better?
-                        let _description = column_selector.next().expect("Unable to find second column");
-                        let download = release_element.select(&Selector::parse("td>button.download-button").unwrap()).next().expect("Unable to find download button");
-                        let archive = download.value().attr("data-link").unwrap().to_string();
-                        Some(AtPack {
-                            family: family.clone(),
-                            version,
-                            chips: chips.clone(),
-                            archive,
-                        })
-                    },
-                    None => {
-                        None
-                    },
+            let chips = Arc::new(chips);
+
+            let mut packs = vec![];
+            for release_element in panel.select(&release_selector) {
+                match self.process_release(&family, &chips, release_element) {
+                    Ok(pack) => packs.extend(pack),
+                    Err(error) => self.tolerate(error)?,
                 }
-                
-            }).collect::<Vec<AtPack>>();
-
-            Some(AtPacksCollection {
-                family: family,
-                chips: chips,
-                packs: versions,
-            })
-        }).collect::<Vec<AtPacksCollection>>();
-        
+            }
+
+            collections.push(AtPacksCollection {
+                family,
+                chips,
+                packs,
+            });
+        }
+
+        collections.iter_mut().for_each(|c| c.sort_packs());
+
         Ok(collections)
     }
-}
\ No newline at end of file
+}
+
+/// SAMV71 packs added out of version order, shared by tests of the crate
+#[cfg(test)]
+pub(crate) fn collection() -> AtPacksCollection {
+    let mut collection =
+        AtPacksCollection::new(Arc::new("SAMV71".to_owned()), Arc::new(vec![]), vec![]);
+    for version in ["4.9.117", "4.12.237", "3.4.84", "4.10.230"] {
+        collection.add_pack(
+            PackVersion::parse(version).unwrap(),
+            format!("Microchip.SAMV71_DFP.{}.atpack", version),
+        );
+    }
+    collection
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use semver::{Version, VersionReq};
+
+    use super::{collection, Grinder, GrinderError};
+
+    static MALFORMED_PAGE: &str = indoc!(
+        r#"
+        <div class="panel-group">
+          <div class="panel">
+            <div class="panel-heading">
+              <h3 class="panel-title"><a>Microchip SAMV71 Series Device Support (4.9.117)</a></h3>
+            </div>
+            <div class="panel-body">
+              <div class="releases">
+                <table class="table">
+                  <tbody>
+                    <tr>
+                      <td>4.9.117 (2022-11-21)</td>
+                      <td>Fixed peripheral definitions.</td>
+                      <td><button class="download-button" data-link="Microchip.SAMV71_DFP.4.9.117.atpack">Download</button></td>
+                    </tr>
+                    <tr>
+                      <td>latest (2022-10-01)</td>
+                      <td>Broken release.</td>
+                      <td><button class="download-button" data-link="broken.atpack">Download</button></td>
+                    </tr>
+                    <tr>
+                      <td>4.8.113 (2022-04-06)</td>
+                      <td>Added configuration data required for XC32.</td>
+                    </tr>
+                  </tbody>
+                </table>
+              </div>
+            </div>
+          </div>
+        </div>
+        "#
+    );
+
+    #[test]
+    fn newest_pack_ignores_page_order() {
+        let collection = collection();
+
+        assert_eq!(
+            collection.newest().map(|p| p.version().semver()),
+            Some(&Version::new(4, 12, 237))
+        );
+    }
+
+    #[test]
+    fn pack_selection_by_requirement() {
+        let collection = collection();
+
+        let exact = VersionReq::parse("=4.9.117").unwrap();
+        assert_eq!(
+            collection.select(&exact).map(|p| p.archive()),
+            Some("Microchip.SAMV71_DFP.4.9.117.atpack")
+        );
+
+        let range = VersionReq::parse(">=4.9, <4.11").unwrap();
+        assert_eq!(
+            collection.select(&range).map(|p| p.version().semver()),
+            Some(&Version::new(4, 10, 230))
+        );
+
+        let missing = VersionReq::parse("^5").unwrap();
+        assert!(collection.select(&missing).is_none());
+    }
+
+    #[test]
+    fn malformed_rows_are_skipped() {
+        let collections = Grinder::new(MALFORMED_PAGE)
+            .process_packs()
+            .expect("Processing failed");
+
+        assert_eq!(collections.len(), 1);
+        let versions = collections[0]
+            .packs()
+            .iter()
+            .map(|p| p.version().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(versions, ["4.9.117"]);
+
+        let pack = &collections[0].packs()[0];
+        assert_eq!(pack.date(), Some("2022-11-21"));
+        assert_eq!(pack.description(), Some("Fixed peripheral definitions."));
+    }
+
+    #[test]
+    fn malformed_rows_fail_strict_processing() {
+        let error = Grinder::new(MALFORMED_PAGE)
+            .strict()
+            .process_packs()
+            .unwrap_err();
+
+        match error.downcast_ref::<GrinderError>() {
+            Some(GrinderError::InvalidVersion { family, row, .. }) => {
+                assert_eq!(family, "SAMV71");
+                assert_eq!(row, "latest (2022-10-01) Broken release. Download");
+            }
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,634 @@
+use std::fs::{self, File};
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use clap::ValueEnum;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use log::warn;
+use reqwest::Url;
+use semver::VersionReq;
+use strum::{Display, VariantNames};
+use tokio::sync::Semaphore;
+use tokio::task;
+
+use crate::cache::Cache;
+use crate::checksum::sha256;
+use crate::descriptor::PackDescriptor;
+use crate::downloader::{DownloadProgress, Downloader};
+use crate::extractor::{
+    extract_resources_from_pack, Artefact, ExtractionError, ExtractionLimits, ResourceTypes,
+    SVD_RESOURCE,
+};
+use crate::grinder::{AtPack, AtPacksCollection, Grinder};
+use crate::indexer::Indexer;
+use crate::retry::RetryPolicy;
+use crate::version::PackVersion;
+use crate::ChipsFamily;
+
+/// Where the list of ATPACKs is taken from
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Display, PartialEq, Eq)]
+#[strum(serialize_all = "kebab-case")]
+pub enum RepositorySource {
+    /// CMSIS pack index, falling back to the HTML page when unavailable
+    #[default]
+    Auto,
+    /// CMSIS pack index (`index.idx`) only
+    Index,
+    /// Repository's HTML page only
+    Html,
+}
+
+/// Families to be harvested
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FamilySelection {
+    /// Families listed in [`ChipsFamily`]
+    #[default]
+    Curated,
+    /// Every family found in the repository
+    All,
+    /// Given families only, each of them has to be present in the repository
+    Only(Vec<String>),
+}
+
+impl FamilySelection {
+    pub fn contains(&self, family: &str) -> bool {
+        match self {
+            FamilySelection::Curated => ChipsFamily::VARIANTS.contains(&family),
+            FamilySelection::All => true,
+            FamilySelection::Only(families) => families.iter().any(|f| f == family),
+        }
+    }
+}
+
+/// Drives the whole process: repository download, grinding and SVD extraction
+pub struct Harvester {
+    downloader: Downloader,
+    source: RepositorySource,
+    families: FamilySelection,
+    pack_versions: Vec<(String, VersionReq)>,
+    all_versions: bool,
+    strict: bool,
+    resource_types: ResourceTypes,
+    limits: ExtractionLimits,
+    jobs: usize,
+    permits: Semaphore,
+    destination: PathBuf,
+}
+
+/// SVDs obtained from a single ATPACK
+#[derive(Debug, Clone)]
+pub struct HarvestedPack {
+    family: String,
+    version: PackVersion,
+    chips: Vec<String>,
+    name: Option<String>,
+    archive: String,
+    archive_sha256: Option<String>,
+    date: Option<String>,
+    description: Option<String>,
+    svds: Vec<String>,
+    artefacts: Vec<Artefact>,
+    reused: bool,
+}
+
+impl HarvestedPack {
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn version(&self) -> &PackVersion {
+        &self.version
+    }
+
+    pub fn chips(&self) -> &[String] {
+        &self.chips
+    }
+
+    /// Name of the pack, as vendor and pack name joined with a dot, eg. `Microchip.SAMV71_DFP`
+    ///
+    /// Taken from the descriptor, unknown when the pack is reused or its descriptor not read.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn archive(&self) -> &str {
+        &self.archive
+    }
+
+    /// SHA-256 of the ATPACK archive, unknown when it wasn't loaded on this run
+    pub fn archive_sha256(&self) -> Option<&str> {
+        self.archive_sha256.as_deref()
+    }
+
+    /// Release date of the ATPACK, when the repository or the descriptor tells it
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    /// Release notes of the ATPACK
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Takes the name and archive checksum of the pack loaded on this run
+    fn identify(&mut self, name: Option<String>, archive_sha256: String) {
+        self.name = name;
+        self.archive_sha256 = Some(archive_sha256);
+    }
+
+    /// Paths of extracted SVDs, relative to the destination directory
+    pub fn svds(&self) -> &[String] {
+        &self.svds
+    }
+
+    /// Other resources extracted on request, with paths relative to the destination directory
+    pub fn artefacts(&self) -> &[Artefact] {
+        &self.artefacts
+    }
+
+    /// Paths of all extracted files, SVDs first
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.svds
+            .iter()
+            .map(String::as_str)
+            .chain(self.artefacts.iter().map(Artefact::path))
+    }
+
+    /// Tells the ATPACK had been harvested on a previous run and wasn't downloaded again
+    pub fn reused(&self) -> bool {
+        self.reused
+    }
+}
+
+/// What's been learnt from an ATPACK while extracting it
+struct Extraction {
+    artefacts: Vec<Artefact>,
+    archive_sha256: String,
+    /// Name from the descriptor, when it could be read
+    name: Option<String>,
+}
+
+/// Outcome of processing a single family found in the repository
+#[derive(Debug, Clone)]
+pub enum FamilyHarvest {
+    /// Selected ATPACKs of the family have been downloaded and their SVDs extracted
+    Harvested(Vec<HarvestedPack>),
+    /// Family was not requested, nothing has been downloaded
+    Ignored(String),
+    /// Family is present in the repository, but doesn't list any ATPACK
+    Empty(String),
+}
+
+/// Splits extracted files into SVDs and other artefacts
+fn sort_artefacts(artefacts: Vec<Artefact>) -> (Vec<String>, Vec<Artefact>) {
+    let (svds, artefacts): (Vec<Artefact>, Vec<Artefact>) = artefacts
+        .into_iter()
+        .partition(|a| a.resource_type() == SVD_RESOURCE);
+    (
+        svds.iter().map(|a| a.path().to_owned()).collect(),
+        artefacts,
+    )
+}
+
+/// Lists files extracted on a previous run, subdirectories tell the resource type
+fn list_artefacts(directory: &Path) -> Result<Vec<Artefact>, Error> {
+    fn visit(directory: &Path, relative: &str, found: &mut Vec<String>) -> Result<(), Error> {
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = if relative.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative, name)
+            };
+            if entry.file_type()?.is_dir() {
+                visit(&entry.path(), &path, found)?;
+            } else {
+                found.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut paths = vec![];
+    visit(directory, "", &mut paths)?;
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| match path.split_once('/') {
+            Some((resource_type, _)) => Artefact::new(resource_type, &path),
+            None => Artefact::new(SVD_RESOURCE, &path),
+        })
+        .collect())
+}
+
+/// Extracts SVDs, and other requested resources, from an ATPACK already present on the disk
+///
+/// Family, version and chips are taken from the pack's descriptor, the repository isn't involved.
+pub fn harvest_file(
+    path: &Path,
+    destination: &Path,
+    resource_types: &ResourceTypes,
+    limits: &ExtractionLimits,
+) -> Result<HarvestedPack, Error> {
+    let mut file =
+        File::open(path).map_err(|e| anyhow!("Unable to open ATPACK {}: {}", path.display(), e))?;
+    let archive_sha256 = sha256(&mut file)?;
+    file.rewind()?;
+    let descriptor = PackDescriptor::from_pack_within(&mut file, limits)?;
+    let (svds, artefacts) = sort_artefacts(extract_resources_from_pack(
+        &mut file,
+        destination,
+        resource_types,
+        limits,
+    )?);
+    let release = descriptor.releases().first();
+
+    Ok(HarvestedPack {
+        family: descriptor.family().to_owned(),
+        version: descriptor.version()?,
+        chips: descriptor.chips(),
+        name: Some(format!("{}.{}", descriptor.vendor(), descriptor.name())),
+        archive: path.display().to_string(),
+        archive_sha256: Some(archive_sha256),
+        date: release.and_then(|r| r.date()).map(str::to_owned),
+        description: release
+            .and_then(|r| r.description())
+            .map(|d| d.split_whitespace().collect::<Vec<&str>>().join(" ")),
+        svds,
+        artefacts,
+        reused: false,
+    })
+}
+
+impl Harvester {
+    pub fn new(repository: Url, destination: &Path) -> Result<Harvester, Error> {
+        Ok(Harvester {
+            downloader: Downloader::new(repository)?,
+            source: RepositorySource::default(),
+            families: FamilySelection::default(),
+            pack_versions: vec![],
+            all_versions: false,
+            strict: false,
+            resource_types: ResourceTypes::default(),
+            limits: ExtractionLimits::default(),
+            jobs: 1,
+            permits: Semaphore::new(1),
+            destination: destination.to_path_buf(),
+        })
+    }
+
+    /// Extracts given types of resources, SVDs only by default
+    pub fn resource_types(mut self, resource_types: ResourceTypes) -> Self {
+        self.resource_types = resource_types;
+        self
+    }
+
+    /// Sizes the content of ATPACKs must fit in when extracted
+    pub fn extraction_limits(mut self, limits: ExtractionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Limits harvesting to given families, names are case insensitive
+    pub fn families(mut self, families: &[String]) -> Self {
+        self.families = FamilySelection::Only(families.iter().map(|f| f.to_uppercase()).collect());
+        self
+    }
+
+    /// Harvests every family found in the repository, not only the curated ones
+    pub fn all_families(mut self) -> Self {
+        self.families = FamilySelection::All;
+        self
+    }
+
+    pub fn repository(&self) -> &Url {
+        self.downloader.address()
+    }
+
+    /// Replaces default download timeouts and retries
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Result<Self, Error> {
+        self.downloader = self.downloader.retry_policy(policy)?;
+        Ok(self)
+    }
+
+    /// Keeps downloads in the cache and reuses them on subsequent runs
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.downloader = self.downloader.cache(cache);
+        self
+    }
+
+    /// Runs purely from the cache, which has to be set up with [`Harvester::cache`]
+    pub fn offline(mut self) -> Self {
+        self.downloader = self.downloader.offline();
+        self
+    }
+
+    /// Reports progress of downloads, see [`DownloadProgress`]
+    pub fn progress(mut self, progress: Arc<dyn DownloadProgress>) -> Self {
+        self.downloader = self.downloader.progress(progress);
+        self
+    }
+
+    /// Fails on malformed releases in the repository's page instead of skipping them
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Selects where the list of ATPACKs is taken from
+    pub fn source(mut self, source: RepositorySource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Pins the family to the newest ATPACK matching the version requirement
+    ///
+    /// Pinned family is harvested even when it's not among the selected families.
+    pub fn pack_version(mut self, family: &str, requirement: VersionReq) -> Self {
+        let family = family.to_uppercase();
+        self.pack_versions.retain(|(f, _)| *f != family);
+        self.pack_versions.push((family, requirement));
+        self
+    }
+
+    /// Harvests every ATPACK version into `<family>/<version>` subdirectories
+    ///
+    /// Versions with their subdirectory already present are not downloaded again.
+    pub fn all_versions(mut self) -> Self {
+        self.all_versions = true;
+        self
+    }
+
+    /// Number of ATPACKs downloaded and extracted at the same time
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self.permits = Semaphore::new(self.jobs);
+        self
+    }
+
+    fn version_requirement(&self, family: &str) -> Option<&VersionReq> {
+        self.pack_versions
+            .iter()
+            .find(|(f, _)| f == family)
+            .map(|(_, r)| r)
+    }
+
+    pub fn is_requested(&self, family: &str) -> bool {
+        self.families.contains(family) || self.version_requirement(family).is_some()
+    }
+
+    /// Checks every explicitly requested or pinned family has been found in the repository
+    fn validate_families(&self, collections: &[AtPacksCollection]) -> Result<(), Error> {
+        let mut families = self
+            .pack_versions
+            .iter()
+            .map(|(f, _)| f)
+            .collect::<Vec<&String>>();
+        if let FamilySelection::Only(ref requested) = self.families {
+            families.extend(requested);
+        }
+
+        let unknown = families
+            .into_iter()
+            .filter(|f| !collections.iter().any(|c| c.family() == f.as_str()))
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            let available = collections
+                .iter()
+                .map(|c| c.family())
+                .collect::<Vec<&str>>();
+            Err(anyhow!(
+                "Families {} not found in the repository, available are {}",
+                unknown.join(", "),
+                available.join(", ")
+            ))
+        }
+    }
+
+    /// Loads repository and returns all ATPACKs collections found there
+    pub async fn catalog(&self) -> Result<Vec<AtPacksCollection>, Error> {
+        let collections = self.load_catalog().await?;
+        self.validate_families(&collections)?;
+        Ok(collections)
+    }
+
+    async fn load_catalog(&self) -> Result<Vec<AtPacksCollection>, Error> {
+        let collections = self.load_source().await?;
+        if collections.is_empty() {
+            return Err(anyhow!(
+                "No ATPACKs found in {}, is it the packs repository?",
+                self.repository()
+            ));
+        }
+        Ok(collections)
+    }
+
+    async fn load_source(&self) -> Result<Vec<AtPacksCollection>, Error> {
+        match self.source {
+            RepositorySource::Index => self.index_catalog().await,
+            RepositorySource::Html => self.html_catalog().await,
+            RepositorySource::Auto => match self.index_catalog().await {
+                Ok(collections) if !collections.is_empty() => Ok(collections),
+                Ok(_) => {
+                    warn!("Pack index lists no known families, falling back to HTML");
+                    self.html_catalog().await
+                }
+                Err(e) => {
+                    warn!("Pack index unusable ({}), falling back to HTML", e);
+                    self.html_catalog().await
+                }
+            },
+        }
+    }
+
+    async fn index_catalog(&self) -> Result<Vec<AtPacksCollection>, Error> {
+        let index = self.downloader.load_index().await?;
+        let mut indexer = Indexer::new(&index);
+        if self.downloader.is_local() {
+            indexer = indexer.local();
+        }
+        indexer.process_packs()
+    }
+
+    async fn html_catalog(&self) -> Result<Vec<AtPacksCollection>, Error> {
+        let repository = self.downloader.load_repository().await?;
+        let mut grinder = Grinder::new(&repository);
+        if self.strict {
+            grinder = grinder.strict();
+        }
+        grinder.process_packs()
+    }
+
+    /// Downloads the selected ATPACKs of the collection and extracts SVDs from them
+    ///
+    /// The newest ATPACK is taken unless the family is pinned with [`Harvester::pack_version`],
+    /// in [`Harvester::all_versions`] mode every ATPACK matching the requirement is taken.
+    pub async fn harvest_collection(
+        &self,
+        collection: &AtPacksCollection,
+    ) -> Result<FamilyHarvest, Error> {
+        if !self.is_requested(collection.family()) {
+            return Ok(FamilyHarvest::Ignored(collection.family().to_owned()));
+        }
+
+        if collection.packs().is_empty() {
+            return Ok(FamilyHarvest::Empty(collection.family().to_owned()));
+        }
+
+        let requirement = self.version_requirement(collection.family());
+        let packs = match (self.all_versions, requirement) {
+            (true, requirement) => collection
+                .packs()
+                .iter()
+                .filter(|p| requirement.is_none_or(|r| r.matches(p.version().semver())))
+                .collect::<Vec<&AtPack>>(),
+            (false, Some(requirement)) => collection.select(requirement).into_iter().collect(),
+            (false, None) => collection.newest().into_iter().collect(),
+        };
+
+        if let (true, Some(requirement)) = (packs.is_empty(), requirement) {
+            let available = collection
+                .packs()
+                .iter()
+                .map(|p| p.version().to_string())
+                .collect::<Vec<String>>();
+            return Err(anyhow!(
+                "No {} ATPACK matches version {}, available are {}",
+                collection.family(),
+                requirement,
+                available.join(", ")
+            ));
+        }
+
+        // Collected first, a lazy iterator of the futures would make `harvest()` lose `Send`
+        let harvests = packs
+            .into_iter()
+            .map(|pack| self.harvest_pack(pack))
+            .collect::<Vec<_>>();
+        let harvested = stream::iter(harvests)
+            .buffered(self.jobs)
+            .try_collect::<Vec<HarvestedPack>>()
+            .await?;
+
+        Ok(FamilyHarvest::Harvested(harvested))
+    }
+
+    async fn harvest_pack(&self, pack: &AtPack) -> Result<HarvestedPack, Error> {
+        let mut harvested = HarvestedPack {
+            family: pack.family().to_owned(),
+            version: pack.version().clone(),
+            chips: pack.chips().to_vec(),
+            name: None,
+            archive: pack.archive().to_owned(),
+            archive_sha256: None,
+            date: pack.date().map(str::to_owned),
+            description: pack.description().map(str::to_owned),
+            svds: vec![],
+            artefacts: vec![],
+            reused: false,
+        };
+
+        if !self.all_versions {
+            let extraction = self.extract_pack(pack, &self.destination).await?;
+            (harvested.svds, harvested.artefacts) = sort_artefacts(extraction.artefacts);
+            harvested.identify(extraction.name, extraction.archive_sha256);
+            return Ok(harvested);
+        }
+
+        let location = format!("{}/{}", pack.family(), pack.version().semver());
+        let directory = self.destination.join(&location);
+
+        let artefacts = if directory.is_dir() {
+            harvested.reused = true;
+            list_artefacts(&directory)?
+        } else {
+            // Extraction goes to a side directory first, so an interrupted run isn't taken as complete
+            let partial = directory.with_file_name(format!("{}.partial", pack.version().semver()));
+            if partial.exists() {
+                fs::remove_dir_all(&partial)?;
+            }
+            // Packs without requested resources still leave a directory, so they are reused
+            fs::create_dir_all(&partial)?;
+            let extraction = self.extract_pack(pack, &partial).await?;
+            fs::rename(&partial, &directory)?;
+            harvested.identify(extraction.name, extraction.archive_sha256);
+            extraction.artefacts
+        };
+
+        let artefacts = artefacts
+            .iter()
+            .map(|a| Artefact::new(a.resource_type(), &format!("{}/{}", location, a.path())))
+            .collect();
+        (harvested.svds, harvested.artefacts) = sort_artefacts(artefacts);
+
+        Ok(harvested)
+    }
+
+    /// Downloads the ATPACK and extracts it on a blocking thread, bounded by the jobs limit
+    async fn extract_pack(&self, pack: &AtPack, destination: &Path) -> Result<Extraction, Error> {
+        let _permit = self.permits.acquire().await?;
+        let mut archive = self.downloader.load_pack(pack).await?;
+        let destination = destination.to_path_buf();
+        let family = pack.family().to_owned();
+        let version = pack.version().clone();
+        let chips = pack.chips().to_vec();
+        let resource_types = self.resource_types.clone();
+        let limits = self.limits.clone();
+        task::spawn_blocking(move || {
+            let archive_sha256 = sha256(&mut archive)?;
+            archive.rewind()?;
+            let mut name = None;
+            match PackDescriptor::from_pack_within(&mut archive, &limits) {
+                Ok(descriptor) => {
+                    name = Some(format!("{}.{}", descriptor.vendor(), descriptor.name()));
+                    for discrepancy in descriptor.cross_check(&version, &chips) {
+                        warn!("ATPACK {} {} is {}", family, version, discrepancy);
+                    }
+                }
+                Err(e) if e.downcast_ref::<ExtractionError>().is_some() => return Err(e),
+                Err(e) => warn!(
+                    "Descriptor of ATPACK {} {} not read: {}",
+                    family, version, e
+                ),
+            }
+            archive.rewind()?;
+            let artefacts =
+                extract_resources_from_pack(&mut archive, &destination, &resource_types, &limits)?;
+            Ok(Extraction {
+                artefacts,
+                archive_sha256,
+                name,
+            })
+        })
+        .await?
+    }
+
+    /// Harvests collections concurrently, up to the jobs limit
+    ///
+    /// Results come in the order of collections, regardless of which one finishes first.
+    pub fn harvest_collections<'a>(
+        &'a self,
+        collections: &'a [AtPacksCollection],
+    ) -> impl Stream<Item = (&'a AtPacksCollection, Result<FamilyHarvest, Error>)> + 'a {
+        stream::iter(collections.iter().map(move |collection| async move {
+            (collection, self.harvest_collection(collection).await)
+        }))
+        .buffered(self.jobs)
+    }
+
+    /// Processes every collection in the repository
+    pub async fn harvest(&self) -> Result<Vec<FamilyHarvest>, Error> {
+        let collections = self.catalog().await?;
+        self.harvest_collections(&collections)
+            .map(|(_, result)| result)
+            .try_collect()
+            .await
+    }
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use log::{info, warn};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::grinder::{is_family_name, AtPacksCollection};
+use crate::version::PackVersion;
+
+/// Name of the CMSIS pack index file in the root of the repository
+pub const PACK_INDEX: &str = "index.idx";
+
+const VENDOR: &str = "Microchip";
+const PACK_SUFFIX: &str = "_DFP";
+const ARCHIVE_EXTENSION: &str = "atpack";
+
+/// Processes CMSIS pack index (`.pidx`/`index.idx`) published by the repository
+pub struct Indexer {
+    input: String,
+    local: bool,
+}
+
+#[derive(Debug, Default)]
+struct IndexEntry {
+    url: String,
+    vendor: String,
+    name: String,
+    version: String,
+    releases: Vec<IndexRelease>,
+}
+
+#[derive(Debug, Default)]
+struct IndexRelease {
+    version: String,
+    date: Option<String>,
+    devices: Vec<String>,
+}
+
+impl IndexEntry {
+    fn from_attributes(element: &BytesStart) -> Result<IndexEntry, Error> {
+        let mut entry = IndexEntry::default();
+        for attribute in element.attributes() {
+            let attribute = attribute?;
+            // Microchip adds `atmel:` prefixed duplicates, only unprefixed ones are standard
+            if attribute.key.prefix().is_some() {
+                continue;
+            }
+            let value = attribute.unescape_value()?.into_owned();
+            match attribute.key.local_name().as_ref() {
+                b"url" => entry.url = value,
+                b"vendor" => entry.vendor = value,
+                b"name" => entry.name = value,
+                b"version" => entry.version = value,
+                _ => {}
+            }
+        }
+        Ok(entry)
+    }
+
+    fn family(&self) -> Option<&str> {
+        if self.vendor != VENDOR {
+            return None;
+        }
+        let family = self.name.strip_suffix(PACK_SUFFIX)?;
+        if is_family_name(family) {
+            Some(family)
+        } else {
+            None
+        }
+    }
+
+    fn archive(&self, version: &str, local: bool) -> String {
+        let file = format!(
+            "{}.{}.{}.{}",
+            self.vendor, self.name, version, ARCHIVE_EXTENSION
+        );
+        if local {
+            return file;
+        }
+        let separator = if self.url.ends_with('/') { "" } else { "/" };
+        format!("{}{}{}", self.url, separator, file)
+    }
+}
+
+/// Value of the unprefixed attribute
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>, Error> {
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        if attribute.key.prefix().is_none() && attribute.key.local_name().as_ref() == name {
+            return Ok(Some(attribute.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+impl Indexer {
+    pub fn new(input: &str) -> Indexer {
+        Indexer {
+            input: input.to_owned(),
+            local: false,
+        }
+    }
+
+    /// Takes ATPACKs from the directory of the index, as in a local mirror of the repository
+    pub fn local(mut self) -> Self {
+        self.local = true;
+        self
+    }
+
+    fn entries(&self) -> Result<Vec<IndexEntry>, Error> {
+        let mut reader = Reader::from_str(&self.input);
+        reader.trim_text(true);
+
+        let mut entries = Vec::<IndexEntry>::new();
+        let mut current: Option<IndexEntry> = None;
+
+        loop {
+            let (element, empty) = match reader.read_event()? {
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                Event::End(e) => {
+                    if e.local_name().as_ref() == b"pdsc" {
+                        entries.extend(current.take());
+                    }
+                    continue;
+                }
+                Event::Eof => break,
+                _ => continue,
+            };
+
+            match element.local_name().as_ref() {
+                b"pdsc" => {
+                    let entry = IndexEntry::from_attributes(&element)?;
+                    if empty {
+                        entries.push(entry);
+                    } else {
+                        current = Some(entry);
+                    }
+                }
+                b"release" => {
+                    if let Some(ref mut entry) = current {
+                        let version = attribute(&element, b"version")?
+                            .ok_or_else(|| anyhow!("Release without version in the pack index"))?;
+                        entry.releases.push(IndexRelease {
+                            version,
+                            date: attribute(&element, b"date")?,
+                            devices: vec![],
+                        });
+                    }
+                }
+                b"device" => {
+                    let release = current.as_mut().and_then(|e| e.releases.last_mut());
+                    if let (Some(release), Some(name)) = (release, attribute(&element, b"name")?) {
+                        release.devices.push(name);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Produces the same collections as the `Grinder` does from the HTML page
+    pub fn process_packs(&self) -> Result<Vec<AtPacksCollection>, Error> {
+        let mut collections = Vec::<AtPacksCollection>::new();
+
+        for entry in self.entries()? {
+            let Some(family) = entry.family() else {
+                continue;
+            };
+
+            let mut versions = if entry.releases.is_empty() {
+                vec![entry.version.clone()]
+            } else {
+                entry.releases.iter().map(|r| r.version.clone()).collect()
+            };
+            if !versions.contains(&entry.version) {
+                versions.push(entry.version.clone());
+            }
+
+            let chips = entry
+                .releases
+                .iter()
+                .find(|r| r.version == entry.version)
+                .or(entry.releases.first())
+                .map(|r| r.devices.clone())
+                .unwrap_or_default();
+
+            let collection = match collections.iter_mut().find(|c| c.family() == family) {
+                Some(collection) => collection,
+                None => {
+                    info!("Found {} family", family);
+                    collections.push(AtPacksCollection::new(
+                        Arc::new(family.to_owned()),
+                        Arc::new(chips),
+                        vec![],
+                    ));
+                    collections.last_mut().unwrap()
+                }
+            };
+
+            for version in versions {
+                let archive = entry.archive(&version, self.local);
+                let date = entry
+                    .releases
+                    .iter()
+                    .find(|r| r.version == version)
+                    .and_then(|r| r.date.clone());
+                let version = match PackVersion::parse(&version) {
+                    Ok(version) => version,
+                    Err(e) => {
+                        warn!("{} of {} in the pack index, skipping it", e, entry.name);
+                        continue;
+                    }
+                };
+                collection
+                    .add_pack(version, archive)
+                    .set_release(date, None);
+            }
+        }
+
+        collections.iter_mut().for_each(|c| c.sort_packs());
+
+        Ok(collections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use semver::Version;
+
+    use super::Indexer;
+
+    static PACK_INDEX: &str = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <index schemaVersion="1.1.0" xmlns:atmel="http://www.atmel.com/schemas/pack-device-atmel-extension">
+          <vendor>Microchip</vendor>
+          <url>https://packs.download.microchip.com/</url>
+          <pindex>
+            <pdsc url="https://packs.download.microchip.com/" vendor="Microchip" name="SAMV71_DFP" version="4.9.117" atmel:name="SAMV71_DFP">
+              <atmel:releases>
+                <atmel:release version="4.9.117">
+                  <atmel:devices>
+                    <atmel:device name="ATSAMV71Q21B" family="SAMV" />
+                    <atmel:device name="ATSAMV71N21B" family="SAMV" />
+                  </atmel:devices>
+                </atmel:release>
+                <atmel:release version="4.10.230" date="2022-11-21" />
+                <atmel:release version="3.4.84" />
+              </atmel:releases>
+            </pdsc>
+            <pdsc url="https://packs.download.microchip.com" vendor="Microchip" name="SAME70_DFP" version="4.10.248" />
+            <pdsc url="https://packs.download.microchip.com/" vendor="Microchip" name="ATtiny_DFP" version="3.1.260" />
+            <pdsc url="https://packs.download.microchip.com/" vendor="Microchip" name="PIC32CX-BZ_DFP" version="1.1.150" />
+            <pdsc url="https://example.com/" vendor="Keil" name="SAMS70_DFP" version="1.0.0" />
+          </pindex>
+        </index>
+        "#
+    );
+
+    #[test]
+    fn check_index_processing() {
+        let collections = Indexer::new(PACK_INDEX)
+            .process_packs()
+            .expect("Index processing failed");
+
+        assert_eq!(collections.len(), 3);
+
+        let samv71 = &collections[0];
+        assert_eq!(samv71.family(), "SAMV71");
+        assert_eq!(samv71.chips(), ["ATSAMV71Q21B", "ATSAMV71N21B"]);
+        let versions = samv71
+            .packs()
+            .iter()
+            .map(|p| p.version().semver().clone())
+            .collect::<Vec<Version>>();
+        assert_eq!(
+            versions,
+            [
+                Version::new(4, 10, 230),
+                Version::new(4, 9, 117),
+                Version::new(3, 4, 84)
+            ]
+        );
+        assert_eq!(
+            samv71.packs()[1].archive(),
+            "https://packs.download.microchip.com/Microchip.SAMV71_DFP.4.9.117.atpack"
+        );
+        assert_eq!(samv71.packs()[0].date(), Some("2022-11-21"));
+        assert_eq!(samv71.packs()[1].date(), None);
+
+        let same70 = &collections[1];
+        assert_eq!(same70.family(), "SAME70");
+        assert!(same70.chips().is_empty());
+        assert_eq!(
+            same70.packs()[0].archive(),
+            "https://packs.download.microchip.com/Microchip.SAME70_DFP.4.10.248.atpack"
+        );
+
+        assert_eq!(collections[2].family(), "PIC32CX-BZ");
+    }
+
+    #[test]
+    fn unparseable_versions_are_skipped() {
+        let index = indoc!(
+            r#"
+            <index>
+              <pindex>
+                <pdsc url="https://packs.download.microchip.com/" vendor="Microchip" name="SAMV71_DFP" version="4.9.117">
+                  <atmel:releases>
+                    <atmel:release version="4.9.117" />
+                    <atmel:release version="unreleased" />
+                  </atmel:releases>
+                </pdsc>
+                <pdsc url="https://packs.download.microchip.com/" vendor="Microchip" name="SAME70_DFP" version="latest" />
+              </pindex>
+            </index>
+            "#
+        );
+
+        let collections = Indexer::new(index)
+            .process_packs()
+            .expect("Index processing failed");
+
+        assert_eq!(collections.len(), 2);
+        let versions = collections[0]
+            .packs()
+            .iter()
+            .map(|p| p.version().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(versions, ["4.9.117"]);
+        assert_eq!(collections[1].family(), "SAME70");
+        assert!(collections[1].packs().is_empty());
+    }
+
+    #[test]
+    fn local_archives_are_relative() {
+        let collections = Indexer::new(PACK_INDEX)
+            .local()
+            .process_packs()
+            .expect("Index processing failed");
+
+        assert_eq!(
+            collections[0].packs()[1].archive(),
+            "Microchip.SAMV71_DFP.4.9.117.atpack"
+        );
+    }
+}
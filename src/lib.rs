@@ -0,0 +1,61 @@
+//! Obtains recent SVDs from Microchip's ATPACKs repository.
+//!
+//! The [`Harvester`] is the high-level entry point: it downloads the repository's
+//! CMSIS pack index or its HTML page, turns it into [`AtPacksCollection`]s and extracts SVD files from
+//! the newest [`AtPack`] of every requested family. The building blocks it uses
+//! are exposed as well for tools that need finer control.
+
+mod cache;
+mod checksum;
+mod descriptor;
+mod downloader;
+mod extractor;
+mod grinder;
+mod harvester;
+mod indexer;
+mod mapper;
+mod retry;
+mod validator;
+mod version;
+
+use strum::{Display, EnumVariantNames};
+
+pub use crate::cache::{Cache, CacheEntry};
+pub use crate::checksum::{sha256, sha256_file};
+pub use crate::descriptor::{
+    DeviceDescription, Discrepancy, Memory, PackDescriptor, Processor, Release,
+};
+pub use crate::downloader::{repository_address, DownloadError, DownloadProgress, Downloader};
+pub use crate::extractor::{
+    extract_resources_from_pack, extract_svds_from_pack, Artefact, ExtractionError,
+    ExtractionLimits, ResourceTypes, RATIO_CHECK_SIZE,
+};
+pub use crate::grinder::{AtPack, AtPacksCollection, Grinder, GrinderError};
+pub use crate::harvester::{
+    harvest_file, FamilyHarvest, FamilySelection, HarvestedPack, Harvester, RepositorySource,
+};
+pub use crate::indexer::Indexer;
+pub use crate::mapper::{AtPacks2SVDsVersionMap, MappingEntry, MAPPING_SCHEMA};
+pub use crate::retry::RetryPolicy;
+pub use crate::validator::SvdValidation;
+pub use crate::version::PackVersion;
+
+/// Curated set of families harvested when none is requested explicitly
+///
+/// Any other family found in the repository can still be requested by its name.
+#[derive(Clone, Debug, Display, EnumVariantNames)]
+#[strum(serialize_all = "SCREAMING-KEBAB-CASE")] // This is tricky for future
+#[allow(non_camel_case_types)]
+pub enum ChipsFamily {
+    SAME51,
+    SAME52,
+    SAME53,
+    SAME54,
+    SAME70,
+    SAMS70,
+    SAMV70,
+    SAMV71,
+    SAMV71_RT,
+    SAMRH707,
+    SAMRH71,
+}
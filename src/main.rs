@@ -1,50 +1,414 @@
-mod downloader;
-mod extractor;
-mod grinder;
-mod mapper;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use std::io::Cursor;
-use std::path::PathBuf;
-
-use anyhow::Error;
-use clap::{Parser, ValueEnum};
-use mapper::AtPacks2SVDsVersionMap;
+use anyhow::{anyhow, Error};
+use atpacks_svd_harvester::{
+    harvest_file, repository_address, sha256_file, AtPacks2SVDsVersionMap, AtPacksCollection,
+    Cache, CacheEntry, ChipsFamily, DownloadProgress, ExtractionLimits, FamilyHarvest,
+    HarvestedPack, Harvester, MappingEntry, RepositorySource, ResourceTypes, RetryPolicy,
+    SvdValidation,
+};
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use clap_verbosity_flag::{Level, Verbosity, WarnLevel};
+use futures::StreamExt;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use log::{info, warn, LevelFilter};
 use reqwest::Url;
-use strum::{Display, EnumVariantNames};
-use tokio;
-
-use crate::downloader::Downloader;
-use crate::extractor::extract_svds_from_pack;
-use crate::grinder::Grinder;
-
-#[derive(ValueEnum, Clone, Debug, Display, EnumVariantNames)]
-#[strum(serialize_all = "SCREAMING-KEBAB-CASE")] // This is tricky for future
-#[allow(non_camel_case_types)]
-enum ChipsFamily {
-    SAME51,
-    SAME52,
-    SAME53,
-    SAME54,
-    SAME70,
-    SAMS70,
-    SAMV70,
-    SAMV71,
-    SAMV71_RT,
-    SAMRH707,
-    SAMRH71,
-}
+use semver::VersionReq;
+use strum::VariantNames;
 
 /// Harvests SVDs by scrapping ATPACKs repository
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Address of the repository with ATPACKs
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    harvest: HarvestArgs,
+
+    /// Controls verbosity levels, -q leaves only errors
+    #[clap(flatten)]
+    verbose: Verbosity<WarnLevel>,
+
+    /// Format of log lines
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints families, chips and ATPACKs versions found in the repository, downloads nothing
+    List(ListArgs),
+    /// Manages downloaded ATPACKs kept in the cache
+    Cache(CacheArgs),
+    /// Extracts SVDs from ATPACKs already present on the disk, the repository isn't used
+    Extract(ExtractArgs),
+}
+
+#[derive(ClapArgs, Debug)]
+struct ExtractArgs {
+    /// ATPACKs to extract SVDs from
+    #[arg(required = true)]
+    atpacks: Vec<PathBuf>,
+
+    #[command(flatten)]
+    extraction: ExtractionArgs,
+
+    /// Destination directory
+    #[arg(short, long)]
+    destination: Option<PathBuf>,
+
+    /// Mapping file to update or create, contains SVD's source ATPACK version
     #[arg(short, long)]
-    repository: Url,
+    mapping: Option<PathBuf>,
+
+    /// Record release date and notes of the ATPACK next to its version in the mapping file
+    #[arg(long, requires = "mapping")]
+    mapping_releases: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+struct ExtractionArgs {
+    /// Resource types to extract as named in ATPACK's manifest (eg. svd, atdf, c.header), or all
+    #[arg(
+        short = 't',
+        long = "resource-type",
+        value_delimiter = ',',
+        default_value = "svd"
+    )]
+    resource_types: Vec<String>,
+
+    /// Megabytes a single file extracted from ATPACK can take
+    #[arg(long, default_value_t = 128)]
+    max_entry_size: u64,
+
+    /// Megabytes all files extracted from ATPACK can take together
+    #[arg(long, default_value_t = 2048)]
+    max_total_size: u64,
+
+    /// Highest ratio of extracted to compressed size of a file over a megabyte, higher suggests a decompression bomb
+    #[arg(long, default_value_t = 100)]
+    max_compression_ratio: u64,
+
+    /// Check extracted SVDs are well-formed CMSIS-SVD, failing ones are left out of the mapping file
+    #[arg(long)]
+    validate: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+struct CacheArgs {
+    /// Cache directory, defaults to the user's cache directory
+    #[arg(short, long)]
+    cache_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Lists cached ATPACKs
+    List,
+    /// Removes older ATPACKs from the cache
+    Prune {
+        /// Number of the newest versions of each family to keep
+        #[arg(short, long, default_value_t = 1)]
+        keep: usize,
+    },
+}
+
+#[derive(ClapArgs, Debug)]
+struct RepositoryArgs {
+    /// Address of the repository with ATPACKs, can be a local directory or file:// URL
+    // Optional only to let subcommands go without it, clap still enforces it where it's used
+    #[arg(short, long, required = true, value_parser = parse_repository)]
+    repository: Option<Url>,
+
+    /// Source of the ATPACKs list: CMSIS pack index or repository's HTML page
+    #[arg(short, long, value_enum, default_value_t = RepositorySource::Auto)]
+    source: RepositorySource,
+
+    /// Cache directory for downloaded ATPACKs, defaults to the user's cache directory
+    #[arg(short, long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Don't use the cache, download everything again
+    #[arg(long, conflicts_with_all = ["cache_dir", "offline"])]
+    no_cache: bool,
+
+    /// Use only ATPACKs and pages already present in the cache
+    #[arg(long)]
+    offline: bool,
+
+    /// Fail on malformed releases in the repository's page instead of skipping them
+    #[arg(long)]
+    strict: bool,
+
+    /// Seconds to wait for connection with the server
+    #[arg(long, default_value_t = 30)]
+    connect_timeout: u64,
+
+    /// Seconds to wait for the next part of the server's response
+    #[arg(long, default_value_t = 60)]
+    read_timeout: u64,
+
+    /// Number of retries of failed downloads, on server errors and connection problems
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Seconds to wait before the first retry, doubled with every next one
+    #[arg(long, default_value_t = 1)]
+    retry_backoff: u64,
+}
+
+/// Progress of a single download
+struct Transfer {
+    bar: Option<ProgressBar>,
+    bytes: u64,
+    started: Instant,
+}
+
+/// Regular output of the harvester with progress of downloads
+///
+/// Progress bars are drawn only on a terminal, otherwise every download is summarized in a line.
+/// Nothing but errors is shown when quiet.
+struct Console {
+    quiet: bool,
+    bars: Option<MultiProgress>,
+    transfers: Mutex<HashMap<Url, Transfer>>,
+}
+
+impl Console {
+    fn new(verbosity: &Verbosity<WarnLevel>) -> Console {
+        let quiet = verbosity.log_level().is_none_or(|l| l < Level::Warn);
+        let bars = (!quiet && io::stderr().is_terminal()).then(MultiProgress::new);
+        Console {
+            quiet,
+            bars,
+            transfers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Prints line of the output, above progress bars if there are any
+    fn print(&self, line: &str) {
+        if self.quiet {
+            return;
+        }
+        match self.bars {
+            Some(ref bars) => bars.suspend(|| println!("{}", line)),
+            None => println!("{}", line),
+        }
+    }
+
+    fn progress_bar(bars: &MultiProgress, address: &Url, length: Option<u64>) -> ProgressBar {
+        let (bar, template) = match length {
+            Some(length) => (
+                ProgressBar::new(length),
+                "{msg:30} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}",
+            ),
+            None => (
+                ProgressBar::new_spinner(),
+                "{msg:30} {spinner} {bytes} {bytes_per_sec}",
+            ),
+        };
+        let style = ProgressStyle::with_template(template)
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+        let name = address
+            .path_segments()
+            .and_then(|mut s| s.next_back())
+            .unwrap_or_default();
+        bars.add(bar.with_style(style).with_message(name.to_owned()))
+    }
+}
+
+impl DownloadProgress for Console {
+    fn started(&self, address: &Url, length: Option<u64>) {
+        if self.quiet {
+            return;
+        }
+        let bar = self
+            .bars
+            .as_ref()
+            .map(|bars| Console::progress_bar(bars, address, length));
+        let mut transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(Transfer { bar: Some(bar), .. }) = transfers.remove(address) {
+            bar.finish_and_clear();
+        }
+        transfers.insert(
+            address.clone(),
+            Transfer {
+                bar,
+                bytes: 0,
+                started: Instant::now(),
+            },
+        );
+    }
+
+    fn advanced(&self, address: &Url, bytes: u64) {
+        let mut transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(transfer) = transfers.get_mut(address) {
+            transfer.bytes += bytes;
+            if let Some(ref bar) = transfer.bar {
+                bar.inc(bytes);
+            }
+        }
+    }
+
+    fn finished(&self, address: &Url) {
+        let transfer = self
+            .transfers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(address);
+        let Some(transfer) = transfer else {
+            return;
+        };
+        if let Some(bar) = transfer.bar {
+            bar.finish_and_clear();
+        }
+        let elapsed = transfer.started.elapsed().as_secs_f64();
+        self.print(&format!(
+            "** Downloaded {}: {} in {:.1}s ({}/s)",
+            address,
+            HumanBytes(transfer.bytes),
+            elapsed,
+            HumanBytes((transfer.bytes as f64 / elapsed.max(0.001)) as u64)
+        ));
+    }
+
+    fn failed(&self, address: &Url) {
+        let transfer = self
+            .transfers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(address);
+        if let Some(Transfer { bar: Some(bar), .. }) = transfer {
+            bar.abandon();
+        }
+    }
+}
+
+/// Sets up logging at the verbosity level, other crates log only at the highest one
+fn init_logging(
+    verbosity: &Verbosity<WarnLevel>,
+    format: LogFormat,
+    console: &Console,
+) -> Result<(), Error> {
+    let level = verbosity.log_level_filter();
+    let mut builder = env_logger::Builder::new();
+    builder
+        .filter_level(match level {
+            LevelFilter::Trace => LevelFilter::Trace,
+            _ => level.min(LevelFilter::Warn),
+        })
+        .filter_module("atpacks_svd_harvester", level);
+    if let LogFormat::Json = format {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+
+    let logger = builder.build();
+    match console.bars {
+        // Keeps log lines from tearing the progress bars
+        Some(ref bars) => LogWrapper::new(bars.clone(), logger).try_init()?,
+        None => {
+            log::set_max_level(logger.filter());
+            log::set_boxed_logger(Box::new(logger))?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_repository(location: &str) -> Result<Url, String> {
+    repository_address(location).map_err(|e| e.to_string())
+}
+
+fn cache_location(cache_dir: &Option<PathBuf>) -> Result<PathBuf, Error> {
+    cache_dir
+        .clone()
+        .or_else(Cache::default_location)
+        .ok_or_else(|| anyhow!("Unable to determine cache directory, use --cache-dir"))
+}
+
+impl RepositoryArgs {
+    fn harvester(&self, destination: &Path, console: &Arc<Console>) -> Result<Harvester, Error> {
+        let repository = self
+            .repository
+            .clone()
+            .ok_or_else(|| anyhow!("Repository address is required"))?;
+        let policy = RetryPolicy {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            read_timeout: Duration::from_secs(self.read_timeout),
+            retries: self.retries,
+            backoff: Duration::from_secs(self.retry_backoff),
+            ..RetryPolicy::default()
+        };
+        let mut harvester = Harvester::new(repository, destination)?
+            .source(self.source)
+            .retry_policy(policy)?
+            .progress(console.clone());
+        if !self.no_cache {
+            harvester = harvester.cache(Cache::new(&cache_location(&self.cache_dir)?));
+        }
+        if self.offline {
+            harvester = harvester.offline();
+        }
+        if self.strict {
+            harvester = harvester.strict();
+        }
+        Ok(harvester)
+    }
+}
+
+#[derive(ClapArgs, Debug)]
+struct HarvestArgs {
+    #[command(flatten)]
+    repository: RepositoryArgs,
+
+    /// Chips family to process (eg. SAMS70), any family found in the repository is accepted
+    #[arg(short, long = "family", long_help = family_help())]
+    families: Vec<String>,
+
+    /// Process every family found in the repository instead of the default ones
+    #[arg(short, long, conflicts_with = "families")]
+    all_families: bool,
+
+    /// ATPACK version requirement for the family (eg. SAMV71==4.9.117 or SAME70=^4.10), newest otherwise, pinned family is always processed
+    #[arg(short, long = "pack-version", value_parser = parse_pack_version)]
+    pack_versions: Vec<(String, VersionReq)>,
+
+    /// Harvest every ATPACK version into <family>/<version> subdirectories, skipping ones already present
+    #[arg(long)]
+    all_versions: bool,
 
-    /// Chips family to process (eg. SAMS70)
-    #[arg(short, long = "family", value_enum)]
-    families: Vec<ChipsFamily>,
+    /// Number of ATPACKs downloaded and extracted at the same time
+    #[arg(short, long, default_value_t = 4)]
+    jobs: usize,
+
+    #[command(flatten)]
+    extraction: ExtractionArgs,
 
     /// Destination directory
     #[arg(short, long)]
@@ -54,28 +418,148 @@ struct Args {
     #[arg(short, long)]
     mapping: Option<PathBuf>,
 
-    /// Controls verbosity levels (unsupported at the moment)
-    #[clap(flatten)]
-    verbose: clap_verbosity_flag::Verbosity,
+    /// Record release date and notes of the ATPACK next to its version in the mapping file
+    #[arg(long, requires = "mapping")]
+    mapping_releases: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    let args = Args::parse();
+#[derive(ClapArgs, Debug)]
+struct ListArgs {
+    #[command(flatten)]
+    repository: RepositoryArgs,
 
-    let downloader = Downloader::new(args.repository.clone())?;
-    let repository = downloader.load_repository().await?;
+    /// Chips family to list (eg. SAMS70), all families are listed when omitted
+    #[arg(short, long = "family")]
+    families: Vec<String>,
 
-    println!(
-        "Downloaded {} characters from the {} website .",
-        repository.len(),
-        args.repository
-    ); // TODO: make log
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+}
 
-    let grinder = Grinder::new(&repository);
-    let collections = grinder.process_packs()?;
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// Human readable table
+    Table,
+    /// JSON document for scripting
+    Json,
+}
 
-    let mut mappings = if let Some(ref path) = args.mapping {
+fn family_help() -> String {
+    format!(
+        "Chips family to process (eg. SAMS70), any family found in the repository is accepted\n\n\
+         When omitted, default families are processed: {}",
+        ChipsFamily::VARIANTS.join(", ")
+    )
+}
+
+fn parse_pack_version(argument: &str) -> Result<(String, VersionReq), String> {
+    let (family, requirement) = argument
+        .split_once('=')
+        .ok_or_else(|| format!("expected <family>=<version requirement>, got {}", argument))?;
+    let requirement = VersionReq::parse(requirement).map_err(|e| e.to_string())?;
+    Ok((family.to_owned(), requirement))
+}
+
+impl ExtractionArgs {
+    fn resource_types(&self) -> ResourceTypes {
+        if self.resource_types.iter().any(|t| t == "all") {
+            ResourceTypes::All
+        } else {
+            ResourceTypes::Only(self.resource_types.clone())
+        }
+    }
+
+    fn limits(&self) -> ExtractionLimits {
+        ExtractionLimits {
+            max_entry_size: self.max_entry_size.saturating_mul(1024 * 1024),
+            max_total_size: self.max_total_size.saturating_mul(1024 * 1024),
+            max_compression_ratio: self.max_compression_ratio,
+        }
+    }
+}
+
+fn print_catalog_table(collections: &[AtPacksCollection]) {
+    for collection in collections {
+        println!("{}: {}", collection.family(), collection.chips().join(", "));
+
+        let width = collection
+            .packs()
+            .iter()
+            .map(|p| p.version().to_string().len())
+            .max()
+            .unwrap_or_default();
+        for pack in collection.packs() {
+            println!(
+                "  {:<width$}  {:<10}  {}",
+                pack.version().to_string(),
+                pack.date().unwrap_or("-"),
+                pack.archive(),
+                width = width
+            );
+            if let Some(description) = pack.description() {
+                println!("  {:<width$}  {}", "", description, width = width);
+            }
+        }
+    }
+}
+
+async fn list(args: ListArgs, console: Arc<Console>) -> Result<(), Error> {
+    let mut harvester = args.repository.harvester(&PathBuf::from("."), &console)?;
+    harvester = if args.families.is_empty() {
+        harvester.all_families()
+    } else {
+        harvester.families(&args.families)
+    };
+
+    let collections = harvester
+        .catalog()
+        .await?
+        .into_iter()
+        .filter(|c| harvester.is_requested(c.family()))
+        .collect::<Vec<AtPacksCollection>>();
+
+    match args.output {
+        OutputFormat::Table => print_catalog_table(&collections),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&collections)?),
+    }
+
+    Ok(())
+}
+
+fn print_cache_entries(entries: &[CacheEntry]) {
+    for entry in entries {
+        println!(
+            "{:<12} {:<10} {:>10}  {}",
+            entry.family(),
+            entry.version().to_string(),
+            entry.size(),
+            entry.path().display()
+        );
+    }
+}
+
+fn cache(args: CacheArgs) -> Result<(), Error> {
+    let cache = Cache::new(&cache_location(&args.cache_dir)?);
+
+    match args.command {
+        CacheCommand::List => print_cache_entries(&cache.entries()?),
+        CacheCommand::Prune { keep } => {
+            let removed = cache.prune(keep)?;
+            print_cache_entries(&removed);
+            println!(
+                "Removed {} ATPACKs from {}",
+                removed.len(),
+                cache.root().display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn load_mappings(mapping: &Option<PathBuf>) -> Result<Option<AtPacks2SVDsVersionMap>, Error> {
+    Ok(if let Some(ref path) = mapping {
         Some(if path.exists() {
             AtPacks2SVDsVersionMap::load(path)?
         } else {
@@ -83,48 +567,245 @@ async fn main() -> Result<(), Error> {
         })
     } else {
         None
+    })
+}
+
+/// Checks SVDs of the pack, telling which of them failed
+/// Checks the extracted SVDs, invalid ones are removed from the destination
+fn validate_svds(pack: &HarvestedPack, destination: &Path, console: &Console) -> Vec<String> {
+    let mut rejected = vec![];
+    for svd in pack.svds() {
+        match SvdValidation::from_file(&destination.join(svd)) {
+            Ok(validation) if validation.is_valid() => console.print(&format!(
+                "*** Validated {}: {} with {} peripherals",
+                svd,
+                validation.device().unwrap_or_default(),
+                validation.peripherals()
+            )),
+            Ok(validation) => {
+                warn!(
+                    "SVD {} is invalid, removed and left out of the mapping: {}",
+                    svd,
+                    validation.errors().join("; ")
+                );
+                rejected.push(svd.clone());
+            }
+            Err(e) => {
+                warn!(
+                    "SVD {} not validated, removed and left out of the mapping: {}",
+                    svd, e
+                );
+                rejected.push(svd.clone());
+            }
+        }
+    }
+    for svd in rejected.iter() {
+        if let Err(e) = fs::remove_file(destination.join(svd)) {
+            warn!("Rejected SVD {} not removed: {}", svd, e);
+        }
+    }
+    rejected
+}
+
+fn record_mappings(
+    mappings: &mut Option<AtPacks2SVDsVersionMap>,
+    pack: &HarvestedPack,
+    destination: &Path,
+    releases: bool,
+    rejected: &[String],
+) {
+    if let Some(ref mut m) = mappings {
+        // Files of earlier harvests are replaced by the rejected ones, which are gone as well
+        rejected.iter().for_each(|r| m.remove(r));
+        let harvested = SystemTime::now();
+        pack.paths()
+            .filter(|p| !rejected.iter().any(|r| r == p))
+            .for_each(|s| {
+                let mut entry = MappingEntry::new(pack.version().clone())
+                    .source(pack.name(), pack.archive(), pack.archive_sha256())
+                    .harvested(harvested);
+                match sha256_file(&destination.join(s)) {
+                    Ok(sha256) => entry = entry.checksum(&sha256),
+                    Err(e) => warn!("Checksum of {} not calculated: {}", s, e),
+                }
+                if releases {
+                    entry = entry.release(pack.date(), pack.description());
+                }
+                m.record(s, entry);
+            });
     };
+}
 
-    for collection in collections {
-        print!("* Obtaining ATPACKs for {} family...", collection.family());
-        if let Some(pack) = collection.packs().first() {
-            if !args.families.is_empty()
-                && !args
-                    .families
-                    .iter()
-                    .any(|e| e.to_string() == collection.family())
-            {
-                println!(" ignoring family not requested.");
-                continue;
+fn save_mappings(
+    mappings: &Option<AtPacks2SVDsVersionMap>,
+    mapping: &Option<PathBuf>,
+) -> Result<(), Error> {
+    if let Some(ref m) = mappings {
+        if let Some(ref path) = mapping {
+            // TODO: chaining unstable https://github.com/rust-lang/rust/issues/53667
+            m.save(path)?;
+        }
+    }
+    Ok(())
+}
+
+async fn harvest(args: HarvestArgs, console: Arc<Console>) -> Result<(), Error> {
+    let destination = args.destination.clone().unwrap_or(PathBuf::from("."));
+    let mut harvester = args.repository.harvester(&destination, &console)?;
+    if args.all_families {
+        harvester = harvester.all_families();
+    } else if !args.families.is_empty() {
+        harvester = harvester.families(&args.families);
+    }
+    for (family, requirement) in args.pack_versions.iter() {
+        harvester = harvester.pack_version(family, requirement.clone());
+    }
+    if args.all_versions {
+        harvester = harvester.all_versions();
+    }
+    harvester = harvester
+        .jobs(args.jobs)
+        .resource_types(args.extraction.resource_types())
+        .extraction_limits(args.extraction.limits());
+
+    let collections = harvester.catalog().await?;
+
+    info!(
+        "Found {} ATPACKs collections on the {} website.",
+        collections.len(),
+        harvester.repository()
+    );
+
+    let mut mappings = load_mappings(&args.mapping)?;
+
+    let mut results = Box::pin(harvester.harvest_collections(&collections));
+    while let Some((collection, result)) = results.next().await {
+        let obtaining = format!("* Obtaining ATPACKs for {} family...", collection.family());
+        match result? {
+            FamilyHarvest::Ignored(_) => {
+                console.print(&format!("{} ignoring family not requested.", obtaining));
+            }
+            FamilyHarvest::Empty(family) => {
+                console.print(&obtaining);
+                warn!("No ATPACKS for the {} family!", family);
             }
+            FamilyHarvest::Harvested(packs) => {
+                console.print(&format!(
+                    "{} chips found are {}",
+                    obtaining,
+                    collection.chips().join(", ")
+                ));
 
-            println!(" chips found are {}", pack.chips().join(", "));
+                for pack in packs {
+                    if pack.reused() {
+                        console.print(&format!(
+                            "** Already harvested {}: {}",
+                            pack.version(),
+                            pack.paths().collect::<Vec<&str>>().join(", ")
+                        ));
+                    } else {
+                        console.print(&format!(
+                            "** Downloaded and extracted {}: {}",
+                            pack.version(),
+                            pack.paths().collect::<Vec<&str>>().join(", ")
+                        ));
+                    }
 
-            let content = downloader.load_file(pack.archive()).await?;
-            let mut reader = Cursor::new(content.as_ref());
-            let svds = extract_svds_from_pack(
-                &mut reader,
-                args.destination.as_ref().unwrap_or(&PathBuf::from(".")),
-            )?;
+                    let rejected = if args.extraction.validate {
+                        validate_svds(&pack, &destination, &console)
+                    } else {
+                        vec![]
+                    };
+                    record_mappings(
+                        &mut mappings,
+                        &pack,
+                        &destination,
+                        args.mapping_releases,
+                        &rejected,
+                    );
+                }
+            }
+        }
+    }
 
-            println!("** Downloaded and extracted: {}", svds.join(", "));
+    save_mappings(&mappings, &args.mapping)
+}
+
+fn extract(args: ExtractArgs, console: &Console) -> Result<(), Error> {
+    let destination = args.destination.clone().unwrap_or(PathBuf::from("."));
+    let mut mappings = load_mappings(&args.mapping)?;
+    let types = args.extraction.resource_types();
+    let limits = args.extraction.limits();
 
-            if let Some(ref mut m) = mappings {
-                svds.iter().for_each(|s| {
-                    m.add_or_update(s, pack.version());
-                });
-            };
+    for atpack in args.atpacks.iter() {
+        let pack = harvest_file(atpack, &destination, &types, &limits)?;
+        console.print(&format!(
+            "* Extracted {} {} from {}: {}",
+            pack.family(),
+            pack.version(),
+            atpack.display(),
+            pack.paths().collect::<Vec<&str>>().join(", ")
+        ));
+        let rejected = if args.extraction.validate {
+            validate_svds(&pack, &destination, console)
         } else {
-            eprintln!("** No ATPACKS for the {} family!", collection.family());
-        }
+            vec![]
+        };
+        record_mappings(
+            &mut mappings,
+            &pack,
+            &destination,
+            args.mapping_releases,
+            &rejected,
+        );
     }
 
-    if let Some(ref mut m) = mappings {
-        if let Some(ref path) = args.mapping {
-            // TODO: chaining unstable https://github.com/rust-lang/rust/issues/53667
-            m.save(path)?;
-        }
+    save_mappings(&mappings, &args.mapping)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let console = Arc::new(Console::new(&args.verbose));
+    init_logging(&args.verbose, args.log_format, &console)?;
+
+    match args.command {
+        Some(Command::List(list_args)) => list(list_args, console).await,
+        Some(Command::Cache(cache_args)) => cache(cache_args),
+        Some(Command::Extract(extract_args)) => extract(extract_args, &console),
+        None => harvest(args.harvest, console).await,
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use atpacks_svd_harvester::{harvest_file, ExtractionLimits, ResourceTypes};
+    use clap_verbosity_flag::{Verbosity, WarnLevel};
+    use tempfile::TempDir;
+
+    use super::{validate_svds, Console};
+
+    #[test]
+    fn invalid_svds_are_removed() {
+        let tempdir = TempDir::with_prefix("atpack-svds").expect("Temporary directory failed");
+        let pack = harvest_file(
+            Path::new("test/data/test.atpack"),
+            tempdir.path(),
+            &ResourceTypes::default(),
+            &ExtractionLimits::default(),
+        )
+        .expect("Extraction failed");
+        let invalid = tempdir.path().join("ATSAMV71Q21B.svd");
+        fs::write(&invalid, "<device></device>").expect("SVD not overwritten");
+
+        let console = Console::new(&Verbosity::<WarnLevel>::new(0, 1));
+        let rejected = validate_svds(&pack, tempdir.path(), &console);
+
+        assert_eq!(rejected, ["ATSAMV71Q21B.svd"]);
+        assert!(!invalid.exists());
+        assert!(tempdir.path().join("ATSAMV71Q20B.svd").is_file());
+    }
 }
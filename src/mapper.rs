@@ -1,61 +1,431 @@
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, path::Path};
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::Path,
+    time::SystemTime,
+};
 
-use semver::Version;
+use crate::version::PackVersion;
+
+/// Schema of mapping files written, the first one had no `schema` field
+pub const MAPPING_SCHEMA: u32 = 2;
+
+/// Version of the ATPACK the SVD comes from, optionally with details of the pack and the file
+///
+/// Written as plain version string unless details are present, so mapping files
+/// without them keep their original form.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MappingEntry {
+    version: PackVersion,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pack: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    harvested: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMappingEntry {
+    Version(PackVersion),
+    Details(Box<MappingEntry>),
+}
+
+impl MappingEntry {
+    pub fn new(version: PackVersion) -> Self {
+        MappingEntry {
+            version,
+            date: None,
+            description: None,
+            sha256: None,
+            pack: None,
+            archive: None,
+            archive_sha256: None,
+            harvested: None,
+        }
+    }
+
+    /// Records when the pack was released and what changed in it
+    pub fn release(mut self, date: Option<&str>, description: Option<&str>) -> Self {
+        self.date = date.map(str::to_owned);
+        self.description = description.map(str::to_owned);
+        self
+    }
+
+    /// Records SHA-256 of the file, telling whether it changed between releases
+    pub fn checksum(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_owned());
+        self
+    }
+
+    /// Records which pack the file comes from and where its archive has been obtained
+    pub fn source(
+        mut self,
+        pack: Option<&str>,
+        archive: &str,
+        archive_sha256: Option<&str>,
+    ) -> Self {
+        self.pack = pack.map(str::to_owned);
+        self.archive = Some(archive.to_owned());
+        self.archive_sha256 = archive_sha256.map(str::to_owned);
+        self
+    }
+
+    /// Records when the file has been harvested
+    pub fn harvested(mut self, time: SystemTime) -> Self {
+        self.harvested = Some(humantime::format_rfc3339_seconds(time).to_string());
+        self
+    }
+
+    pub fn version(&self) -> &PackVersion {
+        &self.version
+    }
+
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn pack(&self) -> Option<&str> {
+        self.pack.as_deref()
+    }
+
+    pub fn archive(&self) -> Option<&str> {
+        self.archive.as_deref()
+    }
+
+    pub fn archive_sha256(&self) -> Option<&str> {
+        self.archive_sha256.as_deref()
+    }
+
+    /// Time of harvesting in RFC 3339 format
+    pub fn harvested_at(&self) -> Option<&str> {
+        self.harvested.as_deref()
+    }
+
+    fn has_details(&self) -> bool {
+        *self != MappingEntry::new(self.version.clone())
+    }
+}
+
+fn serialize_entry<S: Serializer>(entry: &MappingEntry, serializer: S) -> Result<S::Ok, S::Error> {
+    if entry.has_details() {
+        entry.serialize(serializer)
+    } else {
+        entry.version.serialize(serializer)
+    }
+}
+
+fn deserialize_entry<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MappingEntry, D::Error> {
+    Ok(match RawMappingEntry::deserialize(deserializer)? {
+        RawMappingEntry::Version(version) => MappingEntry::new(version),
+        RawMappingEntry::Details(entry) => *entry,
+    })
+}
+
+/// Entry as it's written in the mapping file, either the version alone or with details
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(transparent)]
+struct MappedEntry(
+    #[serde(
+        serialize_with = "serialize_entry",
+        deserialize_with = "deserialize_entry"
+    )]
+    MappingEntry,
+);
+
+fn legacy_schema() -> u32 {
+    1
+}
+
+/// Files are always written in the current schema, whatever they have been loaded from
+fn current_schema<S: Serializer>(_: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u32(MAPPING_SCHEMA)
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AtPacks2SVDsVersionMap {
-    mapping: HashMap<String, Version>,
+    #[serde(default = "legacy_schema", serialize_with = "current_schema")]
+    schema: u32,
+    mapping: BTreeMap<String, MappedEntry>,
+    /// Tells entries have been recorded since the file was loaded
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Default for AtPacks2SVDsVersionMap {
+    fn default() -> Self {
+        AtPacks2SVDsVersionMap {
+            schema: MAPPING_SCHEMA,
+            mapping: BTreeMap::new(),
+            changed: false,
+        }
+    }
 }
 
 impl AtPacks2SVDsVersionMap {
     pub fn new() -> Self {
-        AtPacks2SVDsVersionMap { mapping: HashMap::new() }
+        Self::default()
     }
 
+    /// Loads the mapping file of the current or any older schema, which is upgraded on save
     pub fn load(path: &Path) -> Result<Self> {
         let f = File::open(path)?;
         let m: AtPacks2SVDsVersionMap = serde_json::from_reader(f)?;
+        if m.schema > MAPPING_SCHEMA {
+            return Err(anyhow!(
+                "Mapping file {} has schema {}, newer than supported {}",
+                path.display(),
+                m.schema,
+                MAPPING_SCHEMA
+            ));
+        }
+        debug!(
+            "Loaded {} mappings from {}",
+            m.mapping.len(),
+            path.display()
+        );
         Ok(m)
     }
 
+    /// Writes entries ordered by the file name, leaving the file untouched when nothing changed
     pub fn save(&self, path: &Path) -> Result<()> {
-        let f = File::create(path)?;
-        serde_json::to_writer_pretty(f, self)?;
+        if !self.changed && path.exists() {
+            debug!("Mapping file {} is up to date", path.display());
+            return Ok(());
+        }
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        if fs::read(path).is_ok_and(|current| current == content.as_bytes()) {
+            debug!("Mapping file {} is up to date", path.display());
+            return Ok(());
+        }
+        fs::write(path, content)?;
+        debug!(
+            "Saved {} mappings to {}",
+            self.mapping.len(),
+            path.display()
+        );
         Ok(())
     }
 
-    pub fn add_or_update(&mut self, svd: &str, pack_version: &Version) {
-        self.mapping
-            .entry(svd.to_string())
-            .and_modify(|e| {
-                *e = pack_version.clone();
-            })
-            .or_insert(pack_version.clone());
+    pub fn add_or_update(&mut self, svd: &str, pack_version: &PackVersion) {
+        self.record(svd, MappingEntry::new(pack_version.clone()));
+    }
+
+    /// Adds or replaces the SVD's entry, along with release details it may carry
+    ///
+    /// Harvest time of the previous entry is kept when the file hasn't moved, as well as
+    /// pack name and archive checksum unknown to a reused pack, so repeated runs don't change
+    /// the mapping file.
+    pub fn record(&mut self, svd: &str, mut entry: MappingEntry) {
+        if let Some(MappedEntry(previous)) = self.mapping.get(svd) {
+            if entry.archive == previous.archive {
+                if entry.pack.is_none() {
+                    entry.pack = previous.pack.clone();
+                }
+                if entry.archive_sha256.is_none() {
+                    entry.archive_sha256 = previous.archive_sha256.clone();
+                }
+            }
+            let unmoved = MappingEntry {
+                harvested: previous.harvested.clone(),
+                ..entry.clone()
+            };
+            if unmoved == *previous {
+                return;
+            }
+        }
+        self.mapping.insert(svd.to_string(), MappedEntry(entry));
+        self.changed = true;
+    }
+
+    /// Drops the SVD's entry, if there is one
+    pub fn remove(&mut self, svd: &str) {
+        if self.mapping.remove(svd).is_some() {
+            self.changed = true;
+        }
+    }
+
+    pub fn get(&self, svd: &str) -> Option<&MappingEntry> {
+        self.mapping.get(svd).map(|e| &e.0)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::AtPacks2SVDsVersionMap;
-    use semver::Version;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tempfile::TempDir;
+
+    use super::{AtPacks2SVDsVersionMap, MappingEntry};
+    use crate::version::PackVersion;
 
     #[test]
     fn version_bookkeeping() {
         let mut m = AtPacks2SVDsVersionMap::new();
 
-        m.add_or_update("ATSAMS70Q21B.SVD", &Version::parse("4.40.4").unwrap());
-        m.add_or_update("ATSAMV71Q20B.SVD", &Version::parse("4.41.3").unwrap());
-        m.add_or_update("ATSAMS70Q21B.SVD", &Version::parse("4.42.5").unwrap());
+        m.add_or_update("ATSAMS70Q21B.SVD", &PackVersion::parse("4.40.4").unwrap());
+        m.add_or_update("ATSAMV71Q20B.SVD", &PackVersion::parse("4.41.3").unwrap());
+        m.add_or_update("ATSAMS70Q21B.SVD", &PackVersion::parse("4.42.5").unwrap());
+
+        assert_eq!(
+            m.get("ATSAMS70Q21B.SVD").map(|e| e.version()),
+            Some(&PackVersion::parse("4.42.5").unwrap())
+        );
+        assert_eq!(
+            m.get("ATSAMV71Q20B.SVD").map(|e| e.version()),
+            Some(&PackVersion::parse("4.41.3").unwrap())
+        );
+
+        m.remove("ATSAMV71Q20B.SVD");
+        assert!(m.get("ATSAMV71Q20B.SVD").is_none());
+    }
+
+    #[test]
+    fn original_versions_are_kept() {
+        let mut m = AtPacks2SVDsVersionMap::new();
+        m.add_or_update("ATSAMV71Q21B.svd", &PackVersion::parse("4.10").unwrap());
+
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(
+            json,
+            r#"{"schema":2,"mapping":{"ATSAMV71Q21B.svd":"4.10"}}"#
+        );
+
+        let m: AtPacks2SVDsVersionMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            m.get("ATSAMV71Q21B.svd")
+                .map(|e| e.version().semver().to_string()),
+            Some("4.10.0".to_owned())
+        );
+    }
+
+    #[test]
+    fn release_details_are_optional() {
+        let json = r#"{"mapping":{"A.svd":"4.8.113","B.svd":{"version":"4.9.117","date":"2022-11-21","description":"Fixes"}}}"#;
+        let mut m: AtPacks2SVDsVersionMap = serde_json::from_str(json).unwrap();
+
+        let b = m.get("B.svd").expect("Entry not loaded");
+        assert_eq!(b.version().original(), "4.9.117");
+        assert_eq!(b.date(), Some("2022-11-21"));
+        assert_eq!(b.description(), Some("Fixes"));
+        assert_eq!(m.get("A.svd").and_then(|e| e.date()), None);
 
+        let version = PackVersion::parse("4.10.230").unwrap();
+        m.record(
+            "A.svd",
+            MappingEntry::new(version).release(Some("2023-01-02"), None),
+        );
+        m.mapping.remove("B.svd");
         assert_eq!(
-            m.mapping.get("ATSAMS70Q21B.SVD"),
-            Some(&Version::parse("4.42.5").unwrap())
+            serde_json::to_string(&m).unwrap(),
+            r#"{"schema":2,"mapping":{"A.svd":{"version":"4.10.230","date":"2023-01-02"}}}"#
         );
+    }
+
+    #[test]
+    fn sources_are_recorded() {
+        let json = r#"{"mapping":{"A.svd":"4.8.113"}}"#;
+        let mut m: AtPacks2SVDsVersionMap = serde_json::from_str(json).unwrap();
+        assert_eq!(m.schema, 1);
+        assert_eq!(m.get("A.svd").and_then(|e| e.sha256()), None);
+
+        let entry = MappingEntry::new(PackVersion::parse("4.9.117").unwrap())
+            .checksum("ba7816bf")
+            .source(
+                Some("Microchip.SAMV71_DFP"),
+                "https://packs.download.microchip.com/Microchip.SAMV71_DFP.4.9.117.atpack",
+                Some("ddaf35a1"),
+            )
+            .harvested(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        m.record("A.svd", entry.clone());
+
+        let json = serde_json::to_string(&m).unwrap();
         assert_eq!(
-            m.mapping.get("ATSAMV71Q20B.SVD"),
-            Some(&Version::parse("4.41.3").unwrap())
+            json,
+            concat!(
+                r#"{"schema":2,"mapping":{"A.svd":{"version":"4.9.117","sha256":"ba7816bf","#,
+                r#""pack":"Microchip.SAMV71_DFP","#,
+                r#""archive":"https://packs.download.microchip.com/Microchip.SAMV71_DFP.4.9.117.atpack","#,
+                r#""archive_sha256":"ddaf35a1","harvested":"2023-11-14T22:13:20Z"}}}"#
+            )
         );
+        let m: AtPacks2SVDsVersionMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(m.get("A.svd"), Some(&entry));
+        assert_eq!(entry.harvested_at(), Some("2023-11-14T22:13:20Z"));
+
+        let future = r#"{"schema":3,"mapping":{}}"#;
+        let tempdir = TempDir::with_prefix("mapping").unwrap();
+        let path = tempdir.path().join("svd-versions.json");
+        fs::write(&path, future).unwrap();
+        assert!(AtPacks2SVDsVersionMap::load(&path).is_err());
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        let tempdir = TempDir::with_prefix("mapping").unwrap();
+        let path = tempdir.path().join("svd-versions.json");
+        let version = PackVersion::parse("4.9.117").unwrap();
+        let entry = |time: u64| {
+            MappingEntry::new(version.clone())
+                .checksum("ba7816bf")
+                .harvested(UNIX_EPOCH + Duration::from_secs(time))
+        };
+
+        let mut m = AtPacks2SVDsVersionMap::new();
+        for svd in ["C.svd", "A.svd", "B.svd"] {
+            m.record(svd, entry(1_700_000_000));
+        }
+        m.save(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.ends_with("}\n"));
+        let positions = ["A.svd", "B.svd", "C.svd"].map(|svd| written.find(svd).unwrap());
+        assert!(positions.windows(2).all(|p| p[0] < p[1]));
+
+        // Harvested again later, without any SVD moving
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let mut m = AtPacks2SVDsVersionMap::load(&path).unwrap();
+        m.record("B.svd", entry(1_800_000_000));
+        m.save(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        let mut m = AtPacks2SVDsVersionMap::load(&path).unwrap();
+        m.record("B.svd", entry(1_800_000_000).checksum("cafebabe"));
+        m.save(&path).unwrap();
+        let rewritten = fs::read_to_string(&path).unwrap();
+        assert!(rewritten.contains("cafebabe"));
+        assert!(rewritten.contains(
+            &humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(1_800_000_000))
+                .to_string()
+        ));
+
+        let legacy = r#"{"mapping":{"B.svd":"4.9.117","A.svd":"4.9.117"}}"#;
+        fs::write(&path, legacy).unwrap();
+        let mut m = AtPacks2SVDsVersionMap::load(&path).unwrap();
+        m.add_or_update("A.svd", &version);
+        m.save(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), legacy);
     }
 }
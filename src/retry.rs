@@ -0,0 +1,163 @@
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+/// How the downloader copes with slow and failing servers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Limit for establishing connection with the server
+    pub connect_timeout: Duration,
+    /// Limit for waiting on the response and on every next chunk of it
+    pub read_timeout: Duration,
+    /// Number of attempts made after the first one fails
+    pub retries: u32,
+    /// Delay before the first retry, doubled with every next one
+    pub backoff: Duration,
+    /// Upper bound of the delay between retries, including the one asked by the server
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            connect_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(60),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// No retries, for the cases where failing fast is preferred
+    pub fn none() -> Self {
+        RetryPolicy {
+            retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Delay before the retry following the given failed attempt (counted from 0)
+    ///
+    /// Exponential backoff with jitter, the delay is randomly picked from its upper half,
+    /// so parallel clients don't hit the server at the same moment again.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        exponential / 2 + exponential.mul_f64(fastrand::f64() / 2.0)
+    }
+
+    /// Delay before the retry, the server's `Retry-After` is taken unless it exceeds the bound
+    pub fn retry_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(requested) => requested.min(self.max_backoff),
+            None => self.delay(attempt),
+        }
+    }
+
+    /// Tells the response status is worth another attempt
+    pub fn is_retryable(status: StatusCode) -> bool {
+        status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+/// Delay requested by the server with `Retry-After` header, either in seconds or as a date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::StatusCode;
+
+    use super::{retry_after, RetryPolicy};
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..RetryPolicy::default()
+        };
+
+        for (attempt, limit) in [
+            (0, 100),
+            (1, 200),
+            (2, 400),
+            (3, 800),
+            (4, 1000),
+            (40, 1000),
+        ] {
+            let delay = policy.delay(attempt);
+            assert!(delay <= Duration::from_millis(limit), "{:?}", delay);
+            assert!(delay >= Duration::from_millis(limit / 2), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn server_delay_is_capped() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(
+            policy.retry_delay(0, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+        assert_eq!(
+            policy.retry_delay(0, Some(Duration::from_secs(86_400))),
+            policy.max_backoff
+        );
+
+        let mut headers = HeaderMap::new();
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(86_400));
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+        assert_eq!(
+            policy.retry_delay(2, retry_after(&headers)),
+            policy.max_backoff
+        );
+        assert!(policy.retry_delay(0, None) <= policy.backoff);
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        assert!(RetryPolicy::is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(RetryPolicy::is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!RetryPolicy::is_retryable(StatusCode::NOT_FOUND));
+        assert!(!RetryPolicy::is_retryable(StatusCode::OK));
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(110) && delay <= Duration::from_secs(120));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+}
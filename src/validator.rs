@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{anyhow, Error};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+/// Elements CMSIS-SVD requires in the device, its peripherals and registers
+const DEVICE_ELEMENTS: [&str; 6] = [
+    "name",
+    "version",
+    "description",
+    "addressUnitBits",
+    "width",
+    "peripherals",
+];
+const PERIPHERAL_ELEMENTS: [&str; 2] = ["name", "baseAddress"];
+const REGISTER_ELEMENTS: [&str; 2] = ["name", "addressOffset"];
+
+/// Outcome of checking that an SVD is well-formed and has the structure CMSIS-SVD requires
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SvdValidation {
+    device: Option<String>,
+    peripherals: usize,
+    errors: Vec<String>,
+}
+
+/// Element being read, with what's been found in it so far
+struct Frame {
+    element: String,
+    children: Vec<String>,
+    name: Option<String>,
+    derived: bool,
+}
+
+impl Frame {
+    fn new(element: &BytesStart) -> Frame {
+        Frame {
+            element: String::from_utf8_lossy(element.local_name().as_ref()).to_string(),
+            children: vec![],
+            name: None,
+            derived: element
+                .try_get_attribute("derivedFrom")
+                .ok()
+                .flatten()
+                .is_some(),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("{} {}", self.element, name),
+            None => self.element.clone(),
+        }
+    }
+}
+
+impl SvdValidation {
+    pub fn from_file(path: &Path) -> Result<SvdValidation, Error> {
+        let file = File::open(path)
+            .map_err(|e| anyhow!("Unable to open SVD {}: {}", path.display(), e))?;
+        Ok(SvdValidation::from_reader(BufReader::new(file)))
+    }
+
+    pub fn from_reader(input: impl BufRead) -> SvdValidation {
+        let mut validation = SvdValidation::default();
+        let mut reader = Reader::from_reader(input);
+        reader.trim_text(true);
+
+        let mut buffer = Vec::new();
+        let mut stack = Vec::<Frame>::new();
+        let mut root_seen = false;
+
+        loop {
+            let event = match reader.read_event_into(&mut buffer) {
+                Ok(event) => event,
+                Err(e) => {
+                    validation.errors.push(format!(
+                        "Malformed XML at byte {}: {}",
+                        reader.buffer_position(),
+                        e
+                    ));
+                    return validation;
+                }
+            };
+            match event {
+                Event::Start(e) => {
+                    let frame = Frame::new(&e);
+                    validation.enter(&stack, &frame, &mut root_seen);
+                    stack.push(frame);
+                }
+                Event::Empty(e) => {
+                    let frame = Frame::new(&e);
+                    validation.enter(&stack, &frame, &mut root_seen);
+                    validation.leave(&mut stack, frame);
+                }
+                Event::End(_) => {
+                    if let Some(frame) = stack.pop() {
+                        validation.leave(&mut stack, frame);
+                    }
+                }
+                Event::Text(e) => {
+                    if let [.., parent, current] = stack.as_mut_slice() {
+                        if current.element == "name" {
+                            parent.name = e.unescape().ok().map(|n| n.trim().to_owned());
+                        }
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buffer.clear();
+        }
+
+        if let Some((last, enclosing)) = stack.split_last() {
+            // Named ancestors tell where the file has been cut
+            let location = enclosing
+                .iter()
+                .filter(|f| f.name.is_some())
+                .chain([last])
+                .map(Frame::label)
+                .collect::<Vec<String>>();
+            validation.errors.push(format!(
+                "Unexpected end of file in {}",
+                location.join(" / ")
+            ));
+        } else if !root_seen {
+            validation.errors.push("Document is empty".to_owned());
+        }
+
+        validation
+    }
+
+    fn enter(&mut self, stack: &[Frame], frame: &Frame, root_seen: &mut bool) {
+        if stack.is_empty() {
+            if *root_seen {
+                self.errors
+                    .push(format!("Unexpected {} after the device", frame.element));
+            } else if frame.element != "device" {
+                self.errors.push(format!(
+                    "Root element is {} instead of device",
+                    frame.element
+                ));
+            }
+            *root_seen = true;
+        }
+    }
+
+    fn leave(&mut self, stack: &mut [Frame], frame: Frame) {
+        let parent = stack.last().map(|p| p.element.as_str());
+        let required: &[&str] = match (parent, frame.element.as_str()) {
+            (None, "device") => {
+                self.device = frame.name.clone();
+                &DEVICE_ELEMENTS
+            }
+            (Some("peripherals"), "peripheral") => {
+                self.peripherals += 1;
+                &PERIPHERAL_ELEMENTS
+            }
+            (Some("registers") | Some("cluster"), "register") => &REGISTER_ELEMENTS,
+            _ => &[],
+        };
+        // Derived elements take what they don't define from the element they are derived from
+        let required = if frame.derived { &[][..] } else { required };
+        for element in required {
+            if !frame.children.iter().any(|c| c == element) {
+                self.errors
+                    .push(format!("{} lacks {}", frame.label(), element));
+            }
+        }
+
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(frame.element);
+        }
+    }
+
+    /// Name of the device the SVD describes
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub fn peripherals(&self) -> usize {
+        self.peripherals
+    }
+
+    /// Structural problems found, the first malformed XML stops the check
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use indoc::indoc;
+    use zip::ZipArchive;
+
+    use super::SvdValidation;
+
+    #[test]
+    fn check_svd_from_pack() {
+        let archive =
+            std::fs::File::open("test/data/test.atpack").expect("Test archive not opened");
+        let mut archive = ZipArchive::new(archive).expect("Test archive not read");
+        let mut svd = Vec::new();
+        archive
+            .by_name("samv71b/svd/ATSAMV71Q21B.svd")
+            .expect("SVD not found")
+            .read_to_end(&mut svd)
+            .expect("SVD not read");
+
+        let validation = SvdValidation::from_reader(svd.as_slice());
+        assert_eq!(validation.errors(), [] as [String; 0]);
+        assert_eq!(validation.device(), Some("ATSAMV71Q21B"));
+        assert_eq!(validation.peripherals(), 64);
+
+        let truncated = SvdValidation::from_reader(&svd[..svd.len() / 2]);
+        assert!(!truncated.is_valid());
+        assert_eq!(truncated.device(), None);
+    }
+
+    #[test]
+    fn structural_errors_are_reported() {
+        let svd = indoc!(
+            r#"
+            <device>
+              <name>ATSAMV71Q21B</name>
+              <version>0</version>
+              <addressUnitBits>8</addressUnitBits>
+              <width>32</width>
+              <peripherals>
+                <peripheral>
+                  <name>PIOA</name>
+                  <registers>
+                    <register><name>PIO_PER</name></register>
+                  </registers>
+                </peripheral>
+                <peripheral derivedFrom="PIOA"><name>PIOB</name><baseAddress>0x400E1000</baseAddress></peripheral>
+                <peripheral derivedFrom="PIOA"><name>PIOC</name></peripheral>
+              </peripherals>
+            </device>
+            "#
+        );
+
+        let validation = SvdValidation::from_reader(svd.as_bytes());
+        assert_eq!(validation.device(), Some("ATSAMV71Q21B"));
+        assert_eq!(validation.peripherals(), 3);
+        assert_eq!(
+            validation.errors(),
+            [
+                "register PIO_PER lacks addressOffset",
+                "peripheral PIOA lacks baseAddress",
+                "device ATSAMV71Q21B lacks description",
+            ]
+        );
+
+        let malformed = SvdValidation::from_reader("<device><name>X</nam></device>".as_bytes());
+        assert!(malformed.errors()[0].starts_with("Malformed XML"));
+
+        let foreign = SvdValidation::from_reader("<package/>".as_bytes());
+        assert_eq!(
+            foreign.errors(),
+            ["Root element is package instead of device",]
+        );
+    }
+}
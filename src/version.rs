@@ -0,0 +1,180 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Error};
+use lazy_regex::regex_captures;
+use semver::Version;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of an ATPACK as published by the vendor, along with its semver interpretation
+///
+/// Vendors don't always follow semver, so missing components are filled with zeros and
+/// unexpected suffixes become build metadata. The original string is kept for traceability,
+/// it's what gets displayed and serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PackVersion {
+    original: String,
+    semver: Version,
+}
+
+/// Turns suffix of the version into dot separated build metadata identifiers
+fn build_identifiers(suffix: &str) -> String {
+    suffix
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .filter(|i| !i.is_empty())
+        .collect::<Vec<&str>>()
+        .join(".")
+}
+
+/// Rewrites the version into a semver compliant form
+fn normalize(original: &str) -> Option<String> {
+    let (_, major, minor, patch, pre, rest) = regex_captures!(
+        r"^[vV]?(\d+)(?:\.(\d+))?(?:\.(\d+))?(?:-([0-9A-Za-z-]+(?:\.[0-9A-Za-z-]+)*))?(.*)$",
+        original
+    )?;
+    let number = |n: &str| -> Option<u64> {
+        if n.is_empty() {
+            Some(0)
+        } else {
+            n.parse().ok()
+        }
+    };
+
+    let mut normalized = format!("{}.{}.{}", number(major)?, number(minor)?, number(patch)?);
+    if !pre.is_empty() {
+        normalized.push('-');
+        normalized.push_str(pre);
+    }
+    let build = build_identifiers(rest);
+    if !build.is_empty() {
+        normalized.push('+');
+        normalized.push_str(&build);
+    } else if !rest.trim().is_empty() {
+        return None;
+    }
+    Some(normalized)
+}
+
+impl PackVersion {
+    /// Parses the version tolerating two part versions and vendor specific suffixes
+    pub fn parse(original: &str) -> Result<PackVersion, Error> {
+        let original = original.trim();
+        let semver = match Version::parse(original) {
+            Ok(semver) => semver,
+            Err(e) => normalize(original)
+                .and_then(|n| Version::parse(&n).ok())
+                .ok_or_else(|| anyhow!("Invalid version {}: {}", original, e))?,
+        };
+        Ok(PackVersion {
+            original: original.to_owned(),
+            semver,
+        })
+    }
+
+    /// Version exactly as it was published
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    /// Semver interpretation, used for ordering and matching requirements
+    pub fn semver(&self) -> &Version {
+        &self.semver
+    }
+}
+
+impl From<Version> for PackVersion {
+    fn from(semver: Version) -> Self {
+        PackVersion {
+            original: semver.to_string(),
+            semver,
+        }
+    }
+}
+
+impl FromStr for PackVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PackVersion::parse(s)
+    }
+}
+
+impl Display for PackVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.original)
+    }
+}
+
+impl Ord for PackVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.semver
+            .cmp(&other.semver)
+            .then_with(|| self.original.cmp(&other.original))
+    }
+}
+
+impl PartialOrd for PackVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Serialize for PackVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.original)
+    }
+}
+
+impl<'de> Deserialize<'de> for PackVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let original = String::deserialize(deserializer)?;
+        PackVersion::parse(&original).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::PackVersion;
+
+    fn semver(version: &str) -> String {
+        PackVersion::parse(version)
+            .expect("Version not parsed")
+            .semver()
+            .to_string()
+    }
+
+    #[test]
+    fn vendor_versions_are_normalized() {
+        assert_eq!(semver("4.9.117"), "4.9.117");
+        assert_eq!(semver("1.2"), "1.2.0");
+        assert_eq!(semver("v3"), "3.0.0");
+        assert_eq!(semver("4.10.230-rc"), "4.10.230-rc");
+        assert_eq!(semver("4.10.230-rc.1+b5"), "4.10.230-rc.1+b5");
+        assert_eq!(semver("4.10.230.1"), "4.10.230+1");
+        assert_eq!(semver("4.10.230_build 17"), "4.10.230+build.17");
+        assert_eq!(semver("04.010.0230"), "4.10.230");
+
+        assert!(PackVersion::parse("latest").is_err());
+        assert!(PackVersion::parse("").is_err());
+        assert!(PackVersion::parse("4.10.%").is_err());
+    }
+
+    #[test]
+    fn original_is_kept() {
+        let version = PackVersion::parse("1.2").unwrap();
+
+        assert_eq!(version.original(), "1.2");
+        assert_eq!(version.to_string(), "1.2");
+        assert_eq!(serde_json::to_string(&version).unwrap(), r#""1.2""#);
+        assert_eq!(
+            serde_json::from_str::<PackVersion>(r#""1.2""#).unwrap(),
+            version
+        );
+        assert!(PackVersion::parse("4.10.230-rc").unwrap() < Version::new(4, 10, 230).into());
+    }
+}
@@ -0,0 +1,297 @@
+use std::fs;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use atpacks_svd_harvester::{
+    AtPacksCollection, Cache, DownloadError, DownloadProgress, Downloader, Grinder, Harvester,
+    RepositorySource, RetryPolicy,
+};
+use reqwest::Url;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::{sleep, timeout};
+
+/// Stand-in server answering consecutive connections with the scripted responses
+async fn serve(responses: Vec<&'static str>) -> (Url, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let _ = stream.read(&mut request).await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            // Keeps connection open without sending the rest, while serving the next ones
+            tokio::spawn(async move {
+                if response.is_empty() || response.ends_with("STALL") {
+                    sleep(Duration::from_secs(10)).await;
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    (address, requests)
+}
+
+/// Packs of the local repository, their archives are requested from the stand-in server
+fn collection() -> AtPacksCollection {
+    let page = fs::read_to_string("test/data/repository/index.html").expect("Page not read");
+    Grinder::new(&page)
+        .process_packs()
+        .expect("Page not processed")
+        .remove(0)
+}
+
+/// Loads the collection's ATPACK and reads it whole
+async fn load_pack(downloader: &Downloader) -> Result<Vec<u8>, Error> {
+    let collection = collection();
+    let mut archive = downloader.load_pack(&collection.packs()[0]).await?;
+    let mut content = vec![];
+    archive.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+fn policy(retries: u32) -> RetryPolicy {
+    RetryPolicy {
+        connect_timeout: Duration::from_millis(500),
+        read_timeout: Duration::from_millis(200),
+        retries,
+        backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    }
+}
+
+const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nPK\x05\x06";
+const UNAVAILABLE: &str =
+    "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const TOO_MANY: &str =
+    "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const THROTTLED: &str =
+    "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const NOT_FOUND: &str =
+    "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\nNot Found";
+const EMPTY_PAGE: &str =
+    "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\n<html></html>";
+const STALLED: &str = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\nSTALL";
+const SILENT: &str = "";
+
+#[tokio::test]
+async fn server_errors_are_retried() {
+    let (address, requests) = serve(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(3))
+        .unwrap();
+
+    let content = load_pack(&downloader).await.expect("Download failed");
+
+    assert_eq!(content, b"PK\x05\x06");
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn retries_are_limited() {
+    let (address, requests) = serve(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(1))
+        .unwrap();
+
+    let error = load_pack(&downloader).await.unwrap_err();
+
+    assert!(error.to_string().contains("503"), "{}", error);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn retry_after_is_honored() {
+    let (address, requests) = serve(vec![TOO_MANY, OK]).await;
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(RetryPolicy {
+            max_backoff: Duration::from_secs(2),
+            ..policy(1)
+        })
+        .unwrap();
+
+    let started = std::time::Instant::now();
+    let content = load_pack(&downloader).await.expect("Download failed");
+
+    assert_eq!(content, b"PK\x05\x06");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn retry_after_is_capped() {
+    let (address, requests) = serve(vec![THROTTLED, OK]).await;
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(1))
+        .unwrap();
+
+    let started = std::time::Instant::now();
+    let content = load_pack(&downloader).await.expect("Download failed");
+
+    assert_eq!(content, b"PK\x05\x06");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn stalled_response_times_out() {
+    let (address, requests) = serve(vec![STALLED, OK]).await;
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(1))
+        .unwrap();
+
+    let content = load_pack(&downloader).await.expect("Download failed");
+
+    assert_eq!(content, b"PK\x05\x06");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn silent_server_times_out() {
+    let (address, requests) = serve(vec![SILENT, OK]).await;
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(1))
+        .unwrap();
+
+    // Well before the server gives up on the silent connection
+    let content = timeout(Duration::from_secs(5), load_pack(&downloader))
+        .await
+        .expect("Download not retried")
+        .expect("Download failed");
+
+    assert_eq!(content, b"PK\x05\x06");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn client_errors_are_typed_and_not_retried() {
+    let (address, requests) = serve(vec![NOT_FOUND, OK]).await;
+    let downloader = Downloader::new(address.clone())
+        .unwrap()
+        .retry_policy(policy(3))
+        .unwrap();
+
+    let error = load_pack(&downloader).await.unwrap_err();
+
+    assert_eq!(
+        error.downcast_ref::<DownloadError>(),
+        Some(&DownloadError::Status {
+            address: address.join(collection().packs()[0].archive()).unwrap(),
+            status: reqwest::StatusCode::NOT_FOUND,
+        })
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+/// Records notifications as text, in the order they came
+#[derive(Default)]
+struct Recorder(Mutex<Vec<String>>);
+
+impl DownloadProgress for Recorder {
+    fn started(&self, _address: &Url, length: Option<u64>) {
+        self.0.lock().unwrap().push(format!("started {:?}", length));
+    }
+
+    fn advanced(&self, _address: &Url, bytes: u64) {
+        self.0.lock().unwrap().push(format!("advanced {}", bytes));
+    }
+
+    fn finished(&self, _address: &Url) {
+        self.0.lock().unwrap().push("finished".to_owned());
+    }
+
+    fn failed(&self, _address: &Url) {
+        self.0.lock().unwrap().push("failed".to_owned());
+    }
+}
+
+#[tokio::test]
+async fn progress_is_reported() {
+    let (address, _) = serve(vec![UNAVAILABLE, OK, NOT_FOUND]).await;
+    let recorder = Arc::new(Recorder::default());
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(1))
+        .unwrap()
+        .progress(recorder.clone());
+
+    load_pack(&downloader).await.unwrap();
+    assert!(load_pack(&downloader).await.is_err());
+
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        ["started Some(4)", "advanced 4", "finished", "failed"]
+    );
+}
+
+#[tokio::test]
+async fn page_instead_of_archive_is_refused() {
+    let (address, _) = serve(vec![EMPTY_PAGE]).await;
+    let tempdir = TempDir::with_prefix("atpack-cache").expect("Temporary directory failed");
+    let cache = Cache::new(tempdir.path());
+    let downloader = Downloader::new(address.clone())
+        .unwrap()
+        .retry_policy(policy(0))
+        .unwrap()
+        .cache(Cache::new(tempdir.path()));
+
+    let error = load_pack(&downloader).await.unwrap_err();
+
+    let collection = collection();
+    let pack = &collection.packs()[0];
+    assert_eq!(
+        error.downcast_ref::<DownloadError>(),
+        Some(&DownloadError::NotAnArchive {
+            address: address.join(pack.archive()).unwrap(),
+            found: "HTML or XML document",
+        })
+    );
+    assert!(!cache.pack_path(pack).exists());
+}
+
+#[tokio::test]
+async fn downloaded_packs_are_cached() {
+    let (address, requests) = serve(vec![OK]).await;
+    let tempdir = TempDir::with_prefix("atpack-cache").expect("Temporary directory failed");
+    let downloader = Downloader::new(address)
+        .unwrap()
+        .retry_policy(policy(0))
+        .unwrap()
+        .cache(Cache::new(tempdir.path()));
+
+    for _ in 0..2 {
+        let content = load_pack(&downloader).await.expect("Download failed");
+        assert_eq!(content, b"PK\x05\x06");
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn page_without_packs_is_an_error() {
+    let (address, _) = serve(vec![EMPTY_PAGE]).await;
+    let harvester = Harvester::new(address, std::path::Path::new("."))
+        .unwrap()
+        .source(RepositorySource::Html)
+        .retry_policy(policy(0))
+        .unwrap();
+
+    let error = harvester.catalog().await.unwrap_err();
+
+    assert!(error.to_string().contains("No ATPACKs found"), "{}", error);
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use atpacks_svd_harvester::{
+    repository_address, AtPacks2SVDsVersionMap, FamilyHarvest, Harvester, MappingEntry,
+    RepositorySource, ResourceTypes,
+};
+use semver::{Version, VersionReq};
+use tempfile::TempDir;
+
+const REPOSITORY: &str = "test/data/repository";
+
+#[test]
+fn directory_becomes_file_url() {
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+
+    assert_eq!(address.scheme(), "file");
+    assert!(address.path().ends_with("/test/data/repository/"));
+
+    let trailing =
+        repository_address(address.as_str().trim_end_matches('/')).expect("Repository not found");
+    assert_eq!(trailing, address);
+}
+
+#[test]
+fn pinned_family_is_requested() {
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let requirement = VersionReq::parse("=3.6.144").unwrap();
+
+    let curated = Harvester::new(address.clone(), Path::new("."))
+        .expect("Harvester creation failed")
+        .pack_version("samd21", requirement.clone());
+    assert!(curated.is_requested("SAMD21"));
+    assert!(!curated.is_requested("SAMD51"));
+
+    let selected = Harvester::new(address, Path::new("."))
+        .expect("Harvester creation failed")
+        .families(&["same70".to_owned()])
+        .pack_version("samv71", requirement);
+    assert!(selected.is_requested("SAME70"));
+    assert!(selected.is_requested("SAMV71"));
+    assert!(!selected.is_requested("SAMS70"));
+}
+
+fn assert_send<T: Send>(_: T) {}
+
+#[test]
+fn harvest_can_be_spawned() {
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, Path::new("."))
+        .expect("Harvester creation failed")
+        .all_families();
+
+    assert_send(harvester.catalog());
+    assert_send(harvester.harvest());
+}
+
+#[tokio::test]
+async fn catalog_from_local_repository() {
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, Path::new("."))
+        .expect("Harvester creation failed")
+        .source(RepositorySource::Html)
+        .all_families();
+
+    let collections = harvester.catalog().await.expect("Catalog failed");
+
+    assert_eq!(collections.len(), 1);
+    assert_eq!(collections[0].family(), "SAMV71");
+    assert_eq!(collections[0].chips().len(), 9);
+    assert_eq!(
+        collections[0].packs()[0].version().semver(),
+        &Version::new(4, 8, 113)
+    );
+}
+
+#[tokio::test]
+async fn harvest_from_local_repository() {
+    let tempdir = TempDir::with_prefix("atpack-svds").expect("Temporary directory failed");
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, tempdir.path())
+        .expect("Harvester creation failed")
+        .families(&["samv71".to_owned()]);
+
+    let results = harvester.harvest().await.expect("Harvest failed");
+
+    let FamilyHarvest::Harvested(ref packs) = results[0] else {
+        panic!("SAMV71 not harvested: {:?}", results);
+    };
+    assert_eq!(packs.len(), 1);
+    assert_eq!(packs[0].svds().len(), 9);
+    assert!(tempdir.path().join("ATSAMV71Q21B.svd").is_file());
+    assert_eq!(packs[0].name(), Some("Microchip.SAMV71_DFP"));
+    assert_eq!(
+        packs[0].archive_sha256(),
+        Some("73532afc1d90ff7cd01a93c622fa810c51aaa339ee3371d2d257e1f701e2a25d")
+    );
+}
+
+#[tokio::test]
+async fn harvest_from_local_pack_index() {
+    let tempdir = TempDir::with_prefix("atpack-svds").expect("Temporary directory failed");
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, tempdir.path())
+        .expect("Harvester creation failed")
+        .source(RepositorySource::Index)
+        .families(&["samv71".to_owned()]);
+
+    let results = harvester.harvest().await.expect("Harvest failed");
+
+    let FamilyHarvest::Harvested(ref packs) = results[0] else {
+        panic!("SAMV71 not harvested: {:?}", results);
+    };
+    assert_eq!(packs[0].archive(), "Microchip.SAMV71_DFP.4.8.113.atpack");
+    assert_eq!(packs[0].date(), Some("2022-04-06"));
+    assert_eq!(packs[0].svds().len(), 9);
+    assert!(tempdir.path().join("ATSAMV71Q21B.svd").is_file());
+}
+
+#[tokio::test]
+async fn concurrent_harvest_of_all_versions() {
+    let tempdir = TempDir::with_prefix("atpack-svds").expect("Temporary directory failed");
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, tempdir.path())
+        .expect("Harvester creation failed")
+        .source(RepositorySource::Html)
+        .all_families()
+        .all_versions()
+        .jobs(4);
+
+    for reused in [false, true] {
+        let results = harvester.harvest().await.expect("Harvest failed");
+
+        let FamilyHarvest::Harvested(ref packs) = results[0] else {
+            panic!("SAMV71 not harvested: {:?}", results);
+        };
+        assert_eq!(packs[0].reused(), reused);
+        assert!(packs[0]
+            .svds()
+            .contains(&"SAMV71/4.8.113/ATSAMV71Q21B.svd".to_owned()));
+    }
+
+    assert!(tempdir
+        .path()
+        .join("SAMV71/4.8.113/ATSAMV71Q21B.svd")
+        .is_file());
+}
+
+#[tokio::test]
+async fn harvest_of_pack_without_requested_resources() {
+    let tempdir = TempDir::with_prefix("atpack-svds").expect("Temporary directory failed");
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, tempdir.path())
+        .expect("Harvester creation failed")
+        .source(RepositorySource::Html)
+        .all_families()
+        .all_versions()
+        .resource_types(ResourceTypes::Only(vec!["vhdl".to_owned()]));
+
+    for reused in [false, true] {
+        let results = harvester.harvest().await.expect("Harvest failed");
+
+        let FamilyHarvest::Harvested(ref packs) = results[0] else {
+            panic!("SAMV71 not harvested: {:?}", results);
+        };
+        assert_eq!(packs[0].reused(), reused);
+        assert!(packs[0].svds().is_empty());
+    }
+
+    assert!(tempdir.path().join("SAMV71/4.8.113").is_dir());
+}
+
+#[tokio::test]
+async fn mapping_of_reused_packs_is_unchanged() {
+    let tempdir = TempDir::with_prefix("atpack-svds").expect("Temporary directory failed");
+    let mapping = tempdir.path().join("svd-versions.json");
+    let address = repository_address(REPOSITORY).expect("Repository not found");
+    let harvester = Harvester::new(address, tempdir.path())
+        .expect("Harvester creation failed")
+        .source(RepositorySource::Html)
+        .all_families()
+        .all_versions();
+
+    let mut saved = vec![];
+    for run in 0..2 {
+        let mut m = if mapping.exists() {
+            AtPacks2SVDsVersionMap::load(&mapping).expect("Mapping not loaded")
+        } else {
+            AtPacks2SVDsVersionMap::new()
+        };
+        let results = harvester.harvest().await.expect("Harvest failed");
+
+        let FamilyHarvest::Harvested(ref packs) = results[0] else {
+            panic!("SAMV71 not harvested: {:?}", results);
+        };
+        assert_eq!(packs[0].reused(), run > 0);
+        for pack in packs {
+            for path in pack.paths() {
+                let entry = MappingEntry::new(pack.version().clone())
+                    .source(pack.name(), pack.archive(), pack.archive_sha256())
+                    .harvested(UNIX_EPOCH + Duration::from_secs(1_700_000_000 + run));
+                m.record(path, entry);
+            }
+        }
+        m.save(&mapping).expect("Mapping not saved");
+        let metadata = fs::metadata(&mapping).expect("Mapping not written");
+        saved.push((
+            fs::read_to_string(&mapping).expect("Mapping not read"),
+            metadata.modified().expect("Modification time unknown"),
+        ));
+    }
+
+    assert!(saved[0].0.contains(r#""pack": "Microchip.SAMV71_DFP""#));
+    assert_eq!(saved[0], saved[1]);
+}